@@ -0,0 +1,38 @@
+struct CellBackground {
+  @location(0) pos: vec2<f32>,
+  @location(1) size: vec2<f32>,
+  @location(2) color: vec4<f32>,
+}
+
+struct VertexOutput {
+  @builtin(position) position: vec4<f32>,
+  @location(0) color: vec4<f32>
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_idx: u32,
+    instance: CellBackground
+) -> VertexOutput {
+    let size = instance.size;
+    let pos = instance.pos;
+    let positions = array<vec2<f32>, 6>(
+        vec2(pos.x, pos.y),
+        vec2(pos.x, pos.y - size.y),
+        vec2(pos.x + size.x, pos.y),
+        vec2(pos.x + size.x, pos.y),
+        vec2(pos.x, pos.y - size.y),
+        vec2(pos.x + size.x, pos.y - size.y),
+    );
+
+    var out: VertexOutput;
+    out.color = instance.color;
+    out.position = vec4<f32>(positions[vertex_idx], 0.0, 1.0);
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  return in.color;
+}
@@ -0,0 +1,108 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::Deserialize;
+
+/// The settings of the terminal, read from `$XDG_CONFIG_HOME/nart/config.toml`.
+/// Every field is optional in the file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Maximum amount of lines kept in the scrollback history
+    pub scrollback_lines: usize,
+    pub termios: TermiosConfig,
+    /// What happens to the window once the shell exits
+    pub exit_policy: ExitPolicy,
+    pub shell: ShellConfig,
+}
+
+impl Config {
+    /// Reads the config file, a missing or broken file gives the default settings
+    pub fn load() -> Self {
+        let Some(path) = config_path() else {
+            return Self::default();
+        };
+
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                log::error!("Failed to read {}: {e}", path.display());
+                return Self::default();
+            }
+        };
+
+        toml::from_str(&content).unwrap_or_else(|e| {
+            log::error!("Invalid config at {}: {e}", path.display());
+            Self::default()
+        })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            scrollback_lines: 10_000,
+            termios: TermiosConfig::default(),
+            exit_policy: ExitPolicy::default(),
+            shell: ShellConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExitPolicy {
+    /// The window closes with the shell
+    #[default]
+    Close,
+    /// The window stays open showing the exit status of the shell
+    Hold,
+    /// Like [`ExitPolicy::Hold`], pressing enter starts a new shell
+    Respawn,
+}
+
+/// The program started in the pty
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShellConfig {
+    /// `$SHELL` when unset, then the shell in the passwd entry and `/bin/sh`
+    pub program: Option<String>,
+    pub args: Vec<String>,
+    /// Starts the shell as a login shell, its argv[0] is prefixed with `-`
+    pub login: bool,
+    /// The directory the shell starts in, the one nart was started from when unset
+    pub working_directory: Option<PathBuf>,
+    /// Variables added to the environment of the shell, they override the ones
+    /// nart sets like `TERM`
+    pub env: BTreeMap<String, String>,
+    /// Variables removed from the environment of the shell
+    pub unset_env: Vec<String>,
+}
+
+/// The line discipline settings the pty is created with
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TermiosConfig {
+    /// IUTF8, erasing in canonical mode removes whole UTF-8 characters
+    pub utf8: bool,
+    /// VERASE, the character that erases the previous one in canonical mode
+    pub erase: u8,
+}
+
+impl Default for TermiosConfig {
+    fn default() -> Self {
+        Self {
+            utf8: true,
+            erase: 0x7f,
+        }
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_home.join("nart").join("config.toml"))
+}
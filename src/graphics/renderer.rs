@@ -1,7 +1,14 @@
-use crate::terminal::grid::{TerminalCell, TerminalColor};
+use crate::terminal::{
+    grid::{CellFlags, StyleFlags, TerminalCell, TerminalColor, TerminalStyle},
+    modes::TerminalModes,
+};
 
 use super::{
-    text::{cursor::CursorRenderer, StyledCharacter},
+    text::{
+        background::{BackgroundRect, BackgroundRenderer},
+        cursor::CursorRenderer,
+        StyledCharacter,
+    },
     WgpuContext,
 };
 use std::sync::Arc;
@@ -11,14 +18,19 @@ use winit::{dpi::PhysicalSize, window::Window};
 use super::text::TextRenderer;
 
 const FONT_SIZE: f32 = 16.0;
-const FONT_WIDTH: f32 = FONT_SIZE * 0.6;
 const LINE_HEIGHT: f32 = FONT_SIZE * 1.2;
 
+const DEFAULT_FOREGROUND: super::Color = super::Color::new(255, 255, 255, 255);
+const DEFAULT_BACKGROUND: super::Color = super::Color::new(0, 0, 0, 255);
+
 pub struct Renderer {
     window: Arc<Window>,
     context: WgpuContext<'static>,
     text_renderer: TextRenderer,
     cursor_renderer: CursorRenderer,
+    background_renderer: BackgroundRenderer,
+    default_colors: DefaultColors,
+    cursor_visible: bool,
     size: PhysicalSize<u32>,
 }
 
@@ -33,6 +45,7 @@ impl Renderer {
             cosmic_text::Metrics::new(FONT_SIZE / scale_factor, LINE_HEIGHT / scale_factor);
 
         let cursor_renderer = CursorRenderer::new(&context.device, &context.surf_config);
+        let background_renderer = BackgroundRenderer::new(&context.device, &context.surf_config);
         let mut text_renderer = TextRenderer::new_with_metrics(
             &context.device,
             &context.queue,
@@ -52,12 +65,14 @@ impl Renderer {
             context,
             text_renderer,
             cursor_renderer,
+            background_renderer,
+            default_colors: DefaultColors::new(false),
+            cursor_visible: true,
             size: window_size,
         }
     }
 
     pub fn init_draw(&mut self) {
-        let background_color = super::Color::new(0, 0, 0, 255);
         let mut command_encoder =
             self.context
                 .device
@@ -78,7 +93,7 @@ impl Renderer {
             label: Some("Main render pass"),
             color_attachments: &[Some(RenderPassColorAttachment {
                 ops: wgpu::Operations {
-                    load: LoadOp::Clear(background_color.into()),
+                    load: LoadOp::Clear(self.default_colors.background.into()),
                     store: wgpu::StoreOp::Store,
                 },
                 resolve_target: None,
@@ -87,42 +102,44 @@ impl Renderer {
             ..Default::default()
         });
 
+        self.background_renderer.draw(&mut render_pass);
         self.text_renderer.draw(&mut render_pass);
-        self.cursor_renderer.draw(&mut render_pass);
+        if self.cursor_visible {
+            self.cursor_renderer.draw(&mut render_pass);
+        }
 
         drop(render_pass);
         self.context.queue.submit([command_encoder.finish()]);
 
         surface_texture.present();
-        self.window.request_redraw();
     }
 
-    pub fn write_content(&mut self, content: &Vec<Vec<TerminalCell>>) {
+    pub fn write_content(&mut self, content: &[&[TerminalCell]]) {
+        let cell_size = self.get_cell_size();
+        let backgrounds = self.default_colors.background_runs(content, cell_size);
+        self.background_renderer.update_backgrounds(
+            &self.context.device,
+            &self.context.queue,
+            backgrounds,
+        );
+
         let content = content
             .iter()
-            .flatten()
-            .map(|i| {
-                let color = match i.style.foreground {
-                    TerminalColor::Black => super::Color::new(0, 0, 0, 255),
-                    TerminalColor::Red => super::Color::new(255, 0, 0, 255),
-                    TerminalColor::Green => super::Color::new(0, 255, 0, 255),
-                    TerminalColor::Yellow => super::Color::new(255, 255, 0, 255),
-                    TerminalColor::Blue => super::Color::new(0, 0, 255, 255),
-                    TerminalColor::Magenta => super::Color::new(255, 0, 255, 255),
-                    TerminalColor::Cyan => super::Color::new(0, 255, 255, 255),
-                    TerminalColor::White => super::Color::new(255, 255, 255, 255),
-                    TerminalColor::BrightBlack => super::Color::new(100, 100, 100, 255),
-                    TerminalColor::BrightRed => super::Color::new(255, 100, 100, 255),
-                    TerminalColor::BrightGreen => super::Color::new(100, 255, 100, 255),
-                    TerminalColor::BrightYellow => super::Color::new(255, 255, 100, 255),
-                    TerminalColor::BrightBlue => super::Color::new(100, 100, 255, 255),
-                    TerminalColor::BrightMagenta => super::Color::new(255, 100, 255, 255),
-                    TerminalColor::BrightCyan => super::Color::new(100, 255, 255, 255),
-                    TerminalColor::BrightWhite => super::Color::new(255, 255, 255, 255),
-                    TerminalColor::Rgb(r, g, b) => super::Color::new(r, g, b, 255),
-                };
-
-                StyledCharacter::new(i.content.to_string(), color)
+            .enumerate()
+            .flat_map(|(row, cells)| {
+                let line_break = (row > 0).then(|| {
+                    StyledCharacter::new("\n".to_string(), self.default_colors.foreground, 0)
+                });
+
+                // The glyph of a wide character is drawn over its spacer too
+                let cells = cells
+                    .iter()
+                    .filter(|i| !i.flags.contains(CellFlags::WIDE_SPACER));
+
+                line_break.into_iter().chain(cells.map(|i| {
+                    let color = self.default_colors.foreground_color(&i.style);
+                    StyledCharacter::new(i.content.to_string(), color, i.width())
+                }))
             })
             .collect::<Vec<StyledCharacter>>();
         self.text_renderer.add_text(
@@ -133,11 +150,14 @@ impl Renderer {
     }
 
     pub fn get_cell_size(&mut self) -> (f32, f32) {
-        if let Some(size) = self.text_renderer.get_glyph_size() {
-            return size;
-        }
+        self.text_renderer.get_glyph_size()
+    }
 
-        return (FONT_WIDTH, LINE_HEIGHT);
+    /// Applies the terminal modes that change how the grid is drawn, should be
+    /// called before [`Self::write_content`]
+    pub fn update_modes(&mut self, modes: TerminalModes) {
+        self.cursor_visible = modes.contains(TerminalModes::SHOW_CURSOR);
+        self.default_colors = DefaultColors::new(modes.contains(TerminalModes::REVERSE_VIDEO));
     }
 
     pub fn update_cursor(&mut self, x: f32, y: f32, size: (f32, f32)) {
@@ -171,6 +191,12 @@ impl Renderer {
             (self.size.width, self.size.height),
         );
 
+        self.background_renderer.resize(
+            &self.context.device,
+            &self.context.queue,
+            (self.size.width, self.size.height),
+        );
+
         self.init_draw();
     }
 
@@ -178,3 +204,134 @@ impl Renderer {
         self.window.clone()
     }
 }
+
+/// The colors used by cells without an explicit foreground or background,
+/// swapped while the reverse video mode is set
+#[derive(Debug, Clone, Copy)]
+struct DefaultColors {
+    foreground: super::Color,
+    background: super::Color,
+}
+
+impl DefaultColors {
+    fn new(reverse_video: bool) -> Self {
+        let (foreground, background) = if reverse_video {
+            (DEFAULT_BACKGROUND, DEFAULT_FOREGROUND)
+        } else {
+            (DEFAULT_FOREGROUND, DEFAULT_BACKGROUND)
+        };
+
+        Self {
+            foreground,
+            background,
+        }
+    }
+
+    /// Groups the cells of every row into runs sharing the same non-default background,
+    /// so each run can be drawn as a single rectangle
+    fn background_runs(
+        &self,
+        content: &[&[TerminalCell]],
+        cell_size: (f32, f32),
+    ) -> Vec<BackgroundRect> {
+        let (cell_width, cell_height) = cell_size;
+        let mut rects = Vec::new();
+
+        for (row, cells) in content.iter().enumerate() {
+            let mut run: Option<(usize, super::Color)> = None;
+
+            for (column, cell) in cells.iter().enumerate() {
+                let color = self.background_color(&cell.style);
+                if run.map(|(_, run_color)| run_color) == color {
+                    continue;
+                }
+
+                if let Some((start, run_color)) = run.take() {
+                    rects.push(BackgroundRect {
+                        position: (start as f32 * cell_width, row as f32 * cell_height),
+                        size: ((column - start) as f32 * cell_width, cell_height),
+                        color: run_color,
+                    });
+                }
+
+                run = color.map(|color| (column, color));
+            }
+
+            if let Some((start, run_color)) = run {
+                rects.push(BackgroundRect {
+                    position: (start as f32 * cell_width, row as f32 * cell_height),
+                    size: ((cells.len() - start) as f32 * cell_width, cell_height),
+                    color: run_color,
+                });
+            }
+        }
+
+        rects
+    }
+
+    /// Resolves the background of a cell, `None` when it uses the default background
+    fn background_color(&self, style: &TerminalStyle) -> Option<super::Color> {
+        if style.flags.contains(StyleFlags::INVERSE) {
+            return Some(terminal_color(style.foreground, self.foreground));
+        }
+
+        match style.background {
+            TerminalColor::Default => None,
+            color => Some(terminal_color(color, self.background)),
+        }
+    }
+
+    /// Resolves the color the text of a cell is drawn with
+    fn foreground_color(&self, style: &TerminalStyle) -> super::Color {
+        let (foreground, background) = if style.flags.contains(StyleFlags::INVERSE) {
+            (
+                terminal_color(style.background, self.background),
+                terminal_color(style.foreground, self.foreground),
+            )
+        } else {
+            (
+                terminal_color(style.foreground, self.foreground),
+                terminal_color(style.background, self.background),
+            )
+        };
+
+        if style.flags.contains(StyleFlags::HIDDEN) {
+            return background;
+        }
+
+        if style.flags.contains(StyleFlags::DIM) {
+            let dim = |fg: u8, bg: u8| ((fg as u16 + bg as u16) / 2) as u8;
+            return super::Color::new(
+                dim(foreground.r, background.r),
+                dim(foreground.g, background.g),
+                dim(foreground.b, background.b),
+                foreground.a,
+            );
+        }
+
+        foreground
+    }
+}
+
+fn terminal_color(color: TerminalColor, default: super::Color) -> super::Color {
+    match color {
+        TerminalColor::Default => default,
+        TerminalColor::Black => super::Color::new(0, 0, 0, 255),
+        TerminalColor::Red => super::Color::new(255, 0, 0, 255),
+        TerminalColor::Green => super::Color::new(0, 255, 0, 255),
+        TerminalColor::Yellow => super::Color::new(255, 255, 0, 255),
+        TerminalColor::Blue => super::Color::new(0, 0, 255, 255),
+        TerminalColor::Magenta => super::Color::new(255, 0, 255, 255),
+        TerminalColor::Cyan => super::Color::new(0, 255, 255, 255),
+        TerminalColor::White => super::Color::new(255, 255, 255, 255),
+        TerminalColor::BrightBlack => super::Color::new(100, 100, 100, 255),
+        TerminalColor::BrightRed => super::Color::new(255, 100, 100, 255),
+        TerminalColor::BrightGreen => super::Color::new(100, 255, 100, 255),
+        TerminalColor::BrightYellow => super::Color::new(255, 255, 100, 255),
+        TerminalColor::BrightBlue => super::Color::new(100, 100, 255, 255),
+        TerminalColor::BrightMagenta => super::Color::new(255, 100, 255, 255),
+        TerminalColor::BrightCyan => super::Color::new(100, 255, 255, 255),
+        TerminalColor::BrightWhite => super::Color::new(255, 255, 255, 255),
+        TerminalColor::Rgb(r, g, b) => super::Color::new(r, g, b, 255),
+    }
+}
@@ -0,0 +1,130 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    include_wgsl, vertex_attr_array, BlendState, ColorWrites, Device, Queue, RenderPass,
+    RenderPipeline, SurfaceConfiguration, VertexAttribute, VertexStepMode,
+};
+
+use crate::graphics::{buffer::VertexBuffer, pipeline::PipelineBuilder, Color};
+
+/// A run of cells sharing the same background color, in pixel coordinates
+#[derive(Debug, Clone, Copy)]
+pub struct BackgroundRect {
+    pub position: (f32, f32),
+    pub size: (f32, f32),
+    pub color: Color,
+}
+
+pub struct BackgroundRenderer {
+    pipeline: RenderPipeline,
+    buffer: VertexBuffer<CellBackground>,
+    rects: Vec<BackgroundRect>,
+    surface_size: (f32, f32),
+}
+
+impl BackgroundRenderer {
+    pub fn new(device: &Device, surface_config: &SurfaceConfiguration) -> Self {
+        let shader_desc = include_wgsl!("../../../shaders/background.wgsl");
+        let shader_module = device.create_shader_module(shader_desc);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[],
+            label: Some("Background pipeline layout"),
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = PipelineBuilder::new(device, "Background pipeline")
+            .with_shader(&shader_module)
+            .with_layout(&pipeline_layout)
+            .add_color_target(
+                surface_config.format,
+                Some(BlendState::REPLACE),
+                ColorWrites::ALL,
+            )
+            .add_vertex_layout(
+                &CellBackground::attributes(),
+                std::mem::size_of::<CellBackground>() as u64,
+                VertexStepMode::Instance,
+            )
+            .build();
+
+        let buffer = VertexBuffer::new(device, "Background buffer", None);
+        let surface_size = (surface_config.width as f32, surface_config.height as f32);
+
+        Self {
+            pipeline,
+            buffer,
+            rects: Vec::new(),
+            surface_size,
+        }
+    }
+
+    pub fn update_backgrounds(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        rects: Vec<BackgroundRect>,
+    ) {
+        self.rects = rects;
+        self.write_instances(device, queue);
+    }
+
+    pub fn resize(&mut self, device: &Device, queue: &Queue, new_size: (u32, u32)) {
+        self.surface_size = (new_size.0 as f32, new_size.1 as f32);
+        self.write_instances(device, queue);
+    }
+
+    pub fn draw(&self, render_pass: &mut RenderPass) {
+        if self.rects.is_empty() {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.buffer.raw_buffer().slice(..));
+        render_pass.draw(0..6, 0..self.rects.len() as u32);
+    }
+
+    fn write_instances(&mut self, device: &Device, queue: &Queue) {
+        let instances = self
+            .rects
+            .iter()
+            .map(|rect| CellBackground::from_pixel(rect, self.surface_size))
+            .collect::<Vec<_>>();
+
+        self.buffer.write(device, queue, &instances);
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct CellBackground {
+    position: [f32; 2],
+    size: [f32; 2],
+    color: [f32; 4],
+}
+
+impl CellBackground {
+    fn from_pixel(rect: &BackgroundRect, surface_size: (f32, f32)) -> Self {
+        let [x, y, w, h] = [
+            rect.position.0 / surface_size.0 * 2.0 - 1.0,
+            1.0 - rect.position.1 / surface_size.1 * 2.0,
+            rect.size.0 / surface_size.0 * 2.0,
+            rect.size.1 / surface_size.1 * 2.0,
+        ];
+
+        let color =
+            [rect.color.r, rect.color.g, rect.color.b, rect.color.a].map(|c| c as f32 / 255.0);
+
+        Self {
+            position: [x, y],
+            size: [w, h],
+            color,
+        }
+    }
+
+    pub fn attributes() -> [VertexAttribute; 3] {
+        vertex_attr_array![
+          0 => Float32x2,
+          1 => Float32x2,
+          2 => Float32x4
+        ]
+    }
+}
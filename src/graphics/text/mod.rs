@@ -1,4 +1,5 @@
 pub mod atlas;
+pub mod background;
 pub mod cursor;
 
 use bytemuck::{Pod, Zeroable};
@@ -20,6 +21,8 @@ pub struct TextRenderer {
     glyph_buffer: VertexBuffer<GlyphToRender>,
     cache: Vec<GlyphToRender>,
     swash_cache: SwashCache,
+    /// The cells of every line of the buffer, used to place glyphs at their column
+    line_cells: Vec<Vec<CellSpan>>,
     attributes: cosmic_text::Attrs<'static>,
     pipeline: RenderPipeline,
     atlas_bind_group_layout: BindGroupLayout,
@@ -36,7 +39,8 @@ impl TextRenderer {
     ) -> Self {
         let mut font_system = FontSystem::new();
         let swash_cache = SwashCache::new();
-        let buffer = Buffer::new(&mut font_system, metrics);
+        let mut buffer = Buffer::new(&mut font_system, metrics);
+        buffer.set_wrap(&mut font_system, cosmic_text::Wrap::None);
 
         let atlas = GlyphAtlas::new(2048, device);
         let glyph_buffer = VertexBuffer::new(device, "Glyph vertex buffer", None);
@@ -89,6 +93,7 @@ impl TextRenderer {
             pipeline: render_pipeline,
             surface_size,
             cache: Vec::new(),
+            line_cells: Vec::new(),
             attributes,
         }
     }
@@ -104,8 +109,14 @@ impl TextRenderer {
         }
 
         let mut new_cache = Vec::new();
+        let cell_width = self.cell_width();
 
         for line in self.buffer.layout_runs() {
+            let cells = self.line_cells.get(line.line_i).map(Vec::as_slice);
+            // The offset between the column of the last glyph and where it was laid out,
+            // zero width glyphs like combining marks reuse it to stay on their base
+            let mut shift = 0.0;
+
             for glyph in line.glyphs {
                 let pos = (glyph.x, glyph.y);
                 let cache_key = CacheKey::new(
@@ -122,7 +133,18 @@ impl TextRenderer {
                     .expect("Failed to get glyph image - font not found");
 
                 let placement = glyph_img.placement;
-                let glyph_placement = self.calculate_glyph_position(glyph, &line, placement);
+                let span = cells.and_then(|cells| CellSpan::find(cells, glyph.start));
+                if let Some(span) = span.filter(|_| glyph.w > 0.0) {
+                    let width = span.columns as f32 * cell_width;
+                    let x = span.column as f32 * cell_width + (width - glyph.w) / 2.0;
+                    shift = x - glyph.x;
+                }
+
+                let glyph_placement = if glyph_img.content == cosmic_text::SwashContent::Color {
+                    Self::fit_color_glyph(glyph, &line, placement, span, cell_width)
+                } else {
+                    self.calculate_glyph_position(glyph, &line, placement, shift)
+                };
                 let atlas_id = self.create_atlas_id(cache_key.0);
 
                 let color = glyph
@@ -147,6 +169,7 @@ impl TextRenderer {
             return;
         }
 
+        self.line_cells = CellSpan::from_characters(content);
         self.buffer.set_rich_text(
             &mut self.font_system,
             content.iter().map(|i| {
@@ -223,7 +246,8 @@ impl TextRenderer {
         render_pass.draw(0..6, 0..self.cache.len() as u32);
     }
 
-    pub fn get_glyph_size(&mut self) -> Option<(f32, f32)> {
+    /// The size of a cell from the font metrics, it doesn't need any text laid out
+    pub fn get_glyph_size(&mut self) -> (f32, f32) {
         let width = if let Some(width) = self.buffer.monospace_width() {
             width
         } else {
@@ -233,11 +257,19 @@ impl TextRenderer {
             width
         };
 
-        if let Some(run) = self.buffer.layout_runs().last() {
-            return Some((width, run.line_height));
-        };
+        let height = self
+            .buffer
+            .layout_runs()
+            .last()
+            .map_or(self.buffer.metrics().line_height, |run| run.line_height);
 
-        None
+        (width, height)
+    }
+
+    fn cell_width(&self) -> f32 {
+        self.buffer
+            .monospace_width()
+            .unwrap_or(self.buffer.metrics().font_size * 0.6)
     }
 
     fn calculate_glyph_position(
@@ -245,8 +277,9 @@ impl TextRenderer {
         glyph: &LayoutGlyph,
         line: &cosmic_text::LayoutRun,
         placement: cosmic_text::Placement,
+        shift: f32,
     ) -> (f32, f32, f32, f32) {
-        let x = glyph.x.round();
+        let x = (glyph.x + shift).round();
         let y = line.line_y.round() + glyph.y;
         let width = placement.width as f32;
         let height = placement.height as f32;
@@ -259,6 +292,37 @@ impl TextRenderer {
         )
     }
 
+    /// Scales a color glyph (emoji) to fill the cells of its character, the bitmap
+    /// is rarely the size of the cells
+    fn fit_color_glyph(
+        glyph: &LayoutGlyph,
+        line: &cosmic_text::LayoutRun,
+        placement: cosmic_text::Placement,
+        span: Option<CellSpan>,
+        cell_width: f32,
+    ) -> (f32, f32, f32, f32) {
+        let (width, height) = (placement.width as f32, placement.height as f32);
+        let (x, columns) = match span {
+            Some(span) => (span.column as f32 * cell_width, span.columns as f32),
+            None => (glyph.x.round(), (glyph.w / cell_width).round().max(1.0)),
+        };
+
+        if width == 0.0 || height == 0.0 {
+            return (x, line.line_top, width, height);
+        }
+
+        let cells_width = columns * cell_width;
+        let scale = (cells_width / width).min(line.line_height / height);
+        let (width, height) = (width * scale, height * scale);
+
+        (
+            (x + (cells_width - width) / 2.0).round(),
+            (line.line_top + (line.line_height - height) / 2.0).round(),
+            width,
+            height,
+        )
+    }
+
     fn create_atlas_id(&self, glyph_cache_key: cosmic_text::CacheKey) -> GlyphRectId {
         GlyphRectId::new(glyph_cache_key)
     }
@@ -371,10 +435,58 @@ impl GlyphToRender {
 pub struct StyledCharacter {
     character: String,
     color: crate::graphics::Color,
+    /// The amount of cells the character is drawn across
+    columns: usize,
 }
 
 impl StyledCharacter {
-    pub fn new(character: String, color: crate::graphics::Color) -> Self {
-        Self { character, color }
+    pub fn new(character: String, color: crate::graphics::Color, columns: usize) -> Self {
+        Self {
+            character,
+            color,
+            columns,
+        }
+    }
+}
+
+/// Where the text of a character starts in its buffer line and the cells it covers
+#[derive(Debug, Clone, Copy)]
+struct CellSpan {
+    start: usize,
+    column: usize,
+    columns: usize,
+}
+
+impl CellSpan {
+    /// Splits the characters into buffer lines at every `\n`
+    fn from_characters(content: &[StyledCharacter]) -> Vec<Vec<CellSpan>> {
+        let mut lines = vec![Vec::new()];
+        let (mut start, mut column) = (0, 0);
+
+        for character in content {
+            if character.character == "\n" {
+                lines.push(Vec::new());
+                (start, column) = (0, 0);
+                continue;
+            }
+
+            if let Some(line) = lines.last_mut() {
+                line.push(CellSpan {
+                    start,
+                    column,
+                    columns: character.columns,
+                });
+            }
+            start += character.character.len();
+            column += character.columns;
+        }
+
+        lines
+    }
+
+    /// The span containing the byte at `index` of the line
+    fn find(spans: &[CellSpan], index: usize) -> Option<CellSpan> {
+        let position = spans.partition_point(|span| span.start <= index);
+        position.checked_sub(1).map(|position| spans[position])
     }
 }
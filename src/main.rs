@@ -3,6 +3,7 @@ fn main() {
     crate::ui::window::init_window();
 }
 
+mod config;
 mod graphics;
 mod terminal;
 mod ui;
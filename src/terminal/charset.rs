@@ -0,0 +1,106 @@
+/// The character sets that can be designated into G0-G3 with `ESC ( F` and friends
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    #[default]
+    Ascii,
+    /// The DEC line drawing set, `ESC ( 0`
+    DecSpecialGraphics,
+    /// The United Kingdom national set, only replaces `#` with `£`
+    Uk,
+}
+
+impl Charset {
+    /// Maps the final byte of a designation sequence to its set
+    pub fn from_designator(byte: u8) -> Option<Self> {
+        match byte {
+            b'B' => Some(Self::Ascii),
+            b'0' => Some(Self::DecSpecialGraphics),
+            b'A' => Some(Self::Uk),
+            _ => None,
+        }
+    }
+
+    pub fn map(self, c: char) -> char {
+        match self {
+            Self::Ascii => c,
+            Self::Uk if c == '#' => '£',
+            Self::Uk => c,
+            Self::DecSpecialGraphics => match c {
+                '_' => ' ',
+                '`' => '◆',
+                'a' => '▒',
+                'b' => '␉',
+                'c' => '␌',
+                'd' => '␍',
+                'e' => '␊',
+                'f' => '°',
+                'g' => '±',
+                'h' => '␤',
+                'i' => '␋',
+                'j' => '┘',
+                'k' => '┐',
+                'l' => '┌',
+                'm' => '└',
+                'n' => '┼',
+                'o' => '⎺',
+                'p' => '⎻',
+                'q' => '─',
+                'r' => '⎼',
+                's' => '⎽',
+                't' => '├',
+                'u' => '┤',
+                'v' => '┴',
+                'w' => '┬',
+                'x' => '│',
+                'y' => '≤',
+                'z' => '≥',
+                '{' => 'π',
+                '|' => '≠',
+                '}' => '£',
+                '~' => '·',
+                _ => c,
+            },
+        }
+    }
+}
+
+/// The four designated sets, the one invoked into GL and a pending single shift
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Charsets {
+    sets: [Charset; 4],
+    active: usize,
+    single_shift: Option<usize>,
+}
+
+impl Charsets {
+    /// Maps the intermediate of a designation sequence (`(`, `)`, `*` or `+`)
+    /// to the index of the set it designates
+    pub fn slot(intermediate: u8) -> Option<usize> {
+        match intermediate {
+            b'(' => Some(0),
+            b')' => Some(1),
+            b'*' => Some(2),
+            b'+' => Some(3),
+            _ => None,
+        }
+    }
+
+    pub fn designate(&mut self, slot: usize, charset: Charset) {
+        self.sets[slot] = charset;
+    }
+
+    /// Locking shift, SI invokes G0 and SO invokes G1
+    pub fn invoke(&mut self, slot: usize) {
+        self.active = slot;
+    }
+
+    /// SS2 and SS3, the set is only used for the next printed character
+    pub fn single_shift(&mut self, slot: usize) {
+        self.single_shift = Some(slot);
+    }
+
+    pub fn translate(&mut self, c: char) -> char {
+        let slot = self.single_shift.take().unwrap_or(self.active);
+        self.sets[slot].map(c)
+    }
+}
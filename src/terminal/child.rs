@@ -0,0 +1,81 @@
+use std::{
+    os::fd::{AsFd, BorrowedFd, IntoRawFd, OwnedFd},
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        OnceLock,
+    },
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::OFlag,
+    libc,
+    sys::{
+        signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal},
+        wait::{self, WaitPidFlag, WaitStatus},
+    },
+    unistd::{self, Pid},
+};
+
+/// Write end of the pipe SIGCHLD is forwarded to, read by [`sigchld_fd`] users
+static SIGCHLD_WRITER: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn forward_sigchld(_: libc::c_int) {
+    let errno = Errno::last_raw();
+    let fd = SIGCHLD_WRITER.load(Ordering::Relaxed);
+    if fd >= 0 {
+        // Only async-signal-safe calls here, a full pipe already has a wake up pending
+        unsafe { libc::write(fd, [0u8].as_ptr().cast(), 1) };
+    }
+    Errno::set_raw(errno);
+}
+
+/// A pipe that becomes readable whenever a child process changes state, so the
+/// I/O thread can wait for SIGCHLD in the same `poll` as the master. The handler
+/// is installed the first time this is called
+pub fn sigchld_fd() -> nix::Result<BorrowedFd<'static>> {
+    static READER: OnceLock<OwnedFd> = OnceLock::new();
+
+    if let Some(reader) = READER.get() {
+        return Ok(reader.as_fd());
+    }
+
+    let (reader, writer) = unistd::pipe2(OFlag::O_NONBLOCK | OFlag::O_CLOEXEC)?;
+    // The handler writes to it for as long as the process lives, it's never closed
+    SIGCHLD_WRITER.store(writer.into_raw_fd(), Ordering::Relaxed);
+
+    let action = SigAction::new(
+        SigHandler::Handler(forward_sigchld),
+        SaFlags::SA_RESTART | SaFlags::SA_NOCLDSTOP,
+        SigSet::empty(),
+    );
+    unsafe { signal::sigaction(Signal::SIGCHLD, &action)? };
+
+    Ok(READER.get_or_init(|| reader).as_fd())
+}
+
+/// Empties the SIGCHLD pipe after it was readable
+pub fn drain_sigchld(fd: BorrowedFd) {
+    let mut buffer = [0; 64];
+    while let Ok(1..) = unistd::read(fd, &mut buffer) {}
+}
+
+/// Reaps the child if it exited, `block` waits for it to exit
+pub fn reap(child: Pid, block: bool) -> Option<i32> {
+    let flags = (!block).then_some(WaitPidFlag::WNOHANG);
+
+    loop {
+        match wait::waitpid(child, flags) {
+            Ok(WaitStatus::Exited(_, code)) => return Some(code),
+            // Like shells do, a process killed by a signal exits with 128 + signal
+            Ok(WaitStatus::Signaled(_, signal, _)) => return Some(128 + signal as i32),
+            Ok(WaitStatus::StillAlive) => return None,
+            Ok(_) | Err(Errno::EINTR) if block => continue,
+            Ok(_) => return None,
+            Err(e) => {
+                log::error!("Failed to wait for the shell: {e}");
+                return None;
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError},
+};
+
+/// The first value that isn't a valid `char`, the ids of interned clusters start here
+const INTERNED_START: u32 = char::MAX as u32 + 1;
+
+/// Distinct clusters kept by the interner, past it new clusters lose everything
+/// after their first character instead of growing it
+const INTERNER_CAPACITY: usize = 1 << 16;
+
+/// A grapheme cluster, the content of a cell. Clusters made of a single character
+/// are stored inline and longer ones (combining marks, ZWJ sequences, ...) are
+/// interned, so a cell stays as small as a `char` and `Copy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Grapheme(u32);
+
+impl Grapheme {
+    pub const fn new(c: char) -> Self {
+        Self(c as u32)
+    }
+
+    /// The grapheme of a complete cluster, only clusters that end up in a cell
+    /// should be interned since they are kept for the whole session
+    pub fn intern(cluster: &str) -> Self {
+        let mut chars = cluster.chars();
+        let first = chars.next().unwrap_or(' ');
+        if chars.next().is_none() {
+            return Self::new(first);
+        }
+
+        let mut interner = interner();
+        if let Some(id) = interner.ids.get(cluster) {
+            return Self(*id);
+        }
+
+        if interner.clusters.len() >= INTERNER_CAPACITY {
+            return Self::new(first);
+        }
+
+        let cluster: Arc<str> = cluster.into();
+        let id = INTERNED_START + interner.clusters.len() as u32;
+        interner.clusters.push(cluster.clone());
+        interner.ids.insert(cluster, id);
+
+        Self(id)
+    }
+}
+
+impl fmt::Display for Grapheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match char::from_u32(self.0) {
+            Some(c) => fmt::Write::write_char(f, c),
+            None => {
+                let cluster = interner().clusters[(self.0 - INTERNED_START) as usize].clone();
+                f.write_str(&cluster)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Interner {
+    clusters: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, u32>,
+}
+
+fn interner() -> MutexGuard<'static, Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+
+    INTERNER
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
@@ -1,50 +1,185 @@
-use std::{collections::HashMap, os::fd::RawFd};
+use std::sync::mpsc::Sender;
 
+use nix::pty::Winsize;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
 use vte::Parser;
 
-#[derive(Debug)]
+use super::{
+    charset::{Charset, Charsets},
+    grapheme::Grapheme,
+    modes::TerminalModes,
+    reflow,
+    scrollback::Scrollback,
+};
+
+const DEFAULT_COLUMNS: u32 = 80;
+const DEFAULT_ROWS: u32 = 24;
+const TAB_WIDTH: u32 = 8;
+pub const DEFAULT_TITLE: &str = "Nart";
+/// Same depth limit as the xterm title stack
+const TITLE_STACK_LIMIT: usize = 10;
+/// Characters a cell holds at most, the rest of a longer cluster (like a pile of
+/// combining marks) is dropped
+const MAX_CLUSTER_CHARS: usize = 16;
+
 pub struct TerminalGrid {
     pub rows: u32,
     pub columns: u32,
     pub cell_size: (f32, f32),
+    /// Kept between reads so sequences split across two reads are parsed correctly
+    parser: Parser,
     cells: Vec<Vec<TerminalCell>>,
+    /// The screen not being displayed, the primary one while the alternate
+    /// screen is active and the other way around
+    inactive_cells: Vec<Vec<TerminalCell>>,
+    alternate_screen_active: bool,
+    history: Scrollback,
+    /// How many lines the view is scrolled back into the history, 0 shows the screen
+    display_offset: usize,
     cursor: TerminalCursor,
+    /// First and last rows of the scroll region (DECSTBM)
+    scroll_top: u32,
+    scroll_bottom: u32,
+    /// First and last columns of the left and right margins (DECSLRM), only
+    /// used while the left and right margin mode is set
+    left_margin: u32,
+    right_margin: u32,
+    /// Columns with a tab stop set, one entry per column
+    tab_stops: Vec<bool>,
+    /// The state saved by DECSC, each screen has its own
+    saved_cursor: Option<SavedCursor>,
+    inactive_saved_cursor: Option<SavedCursor>,
+    /// The last character printed, repeated by REP
+    last_printed: Option<char>,
+    /// The cluster of the last printed cell while characters are joining it, it's
+    /// only interned into the cell once complete
+    cluster: Option<PendingCluster>,
+    /// Set after printing into the last column, the next printed character
+    /// wraps to the following line instead of overwriting it (like xterm does)
+    wrap_pending: bool,
     width: u32,
     height: u32,
     current_style: TerminalStyle,
-    fd: RawFd,
+    charsets: Charsets,
+    modes: TerminalModes,
+    title: Option<String>,
+    icon_name: Option<String>,
+    title_stack: Vec<(Option<String>, Option<String>)>,
+    title_changed: bool,
+    /// Replies to the queries of the running program, written back to the PTY
+    responses: Sender<String>,
 }
 
 impl TerminalGrid {
-    pub fn new(fd: RawFd) -> Self {
+    pub fn new(scrollback_lines: usize, responses: Sender<String>) -> Self {
         Self {
-            fd,
+            responses,
             width: 0,
             height: 0,
-            rows: 0,
-            columns: 0,
-            cells: Vec::new(),
+            rows: DEFAULT_ROWS,
+            columns: DEFAULT_COLUMNS,
+            parser: Parser::new(),
+            cells: vec![blank_row(DEFAULT_COLUMNS); DEFAULT_ROWS as usize],
+            inactive_cells: vec![blank_row(DEFAULT_COLUMNS); DEFAULT_ROWS as usize],
+            alternate_screen_active: false,
+            history: Scrollback::new(scrollback_lines),
+            display_offset: 0,
             cell_size: (0.0, 0.0),
             cursor: TerminalCursor(0, 0),
+            scroll_top: 0,
+            scroll_bottom: DEFAULT_ROWS - 1,
+            left_margin: 0,
+            right_margin: DEFAULT_COLUMNS - 1,
+            tab_stops: default_tab_stops(0..DEFAULT_COLUMNS),
+            saved_cursor: None,
+            inactive_saved_cursor: None,
+            last_printed: None,
+            cluster: None,
+            wrap_pending: false,
             current_style: TerminalStyle::default(),
+            charsets: Charsets::default(),
+            modes: TerminalModes::default(),
+            title: None,
+            icon_name: None,
+            title_stack: Vec::new(),
+            title_changed: false,
         }
     }
 
-    pub fn get_content(&self) -> &Vec<Vec<TerminalCell>> {
-        &self.cells
+    /// Returns the rows currently in view, taking into account how far the view
+    /// is scrolled back into the history
+    pub fn get_content(&self) -> Vec<&[TerminalCell]> {
+        let columns = self.columns as usize;
+        let screen_rows = self.cells.len().saturating_sub(self.display_offset);
+
+        self.history
+            .last_lines(self.display_offset)
+            .take(self.cells.len())
+            .map(|line| &line[..line.len().min(columns)])
+            .chain(self.cells[..screen_rows].iter().map(|row| row.as_slice()))
+            .collect()
     }
 
     pub fn get_cursor(&self) -> (f32, f32) {
-        self.cursor
-            .get_pixel_coords(self.cell_size.0, self.cell_size.1)
+        let (x, y) = self
+            .cursor
+            .get_pixel_coords(self.cell_size.0, self.cell_size.1);
+
+        (x, y + self.display_offset as f32 * self.cell_size.1)
+    }
+
+    pub fn modes(&self) -> TerminalModes {
+        self.modes
+    }
+
+    /// Returns the window title set by the running program, only if it changed
+    /// since the last call
+    pub fn take_title_update(&mut self) -> Option<&str> {
+        if !std::mem::take(&mut self.title_changed) {
+            return None;
+        }
+
+        Some(self.title.as_deref().unwrap_or(DEFAULT_TITLE))
+    }
+
+    /// Moves the view `delta` lines into the history (positive values) or back
+    /// towards the screen (negative values)
+    pub fn scroll_display(&mut self, delta: i32) {
+        // The history belongs to the primary screen only
+        if self.alternate_screen_active {
+            return;
+        }
+
+        let offset = self.display_offset as i64 + delta as i64;
+        self.display_offset = offset.clamp(0, self.history.len() as i64) as usize;
+    }
+
+    pub fn reset_display(&mut self) {
+        self.display_offset = 0;
+    }
+
+    pub fn is_display_scrolled(&self) -> bool {
+        self.display_offset != 0
     }
 
     pub fn update(&mut self, data: &[u8]) {
-        let mut parser = Parser::new();
-        let _ = parser.advance_until_terminated(self, data);
+        // The parser needs the grid as its performer, so it's moved out while it runs
+        let mut parser = std::mem::take(&mut self.parser);
+        parser.advance(self, data);
+        self.parser = parser;
+        self.finish_cluster();
     }
 
+    /// Fits the grid to a window of `width` x `height` pixels, the program has to
+    /// be told about the new size with [`Self::winsize`] afterwards. An empty
+    /// window (minimized) keeps the current size
     pub fn resize(&mut self, width: u32, height: u32) {
+        if self.cell_size.0 <= 0.0 || self.cell_size.1 <= 0.0 || width == 0 || height == 0 {
+            return;
+        }
+
         self.width = width;
         self.height = height;
         let (columns, rows) = (
@@ -52,368 +187,2349 @@ impl TerminalGrid {
             self.height as f32 / self.cell_size.1,
         );
 
-        nix::ioctl_write_int_bad!(tiocswinsz, nix::libc::TIOCSWINSZ);
+        self.resize_cells((columns as u32).max(1), (rows as u32).max(1));
+    }
 
-        let winsize = nix::pty::Winsize {
+    /// The size of the grid as reported to the program, in cells and in pixels
+    pub fn winsize(&self) -> Winsize {
+        Winsize {
             ws_row: self.rows as u16,
             ws_col: self.columns as u16,
-            ws_xpixel: 0,
-            ws_ypixel: 0,
-        };
+            ws_xpixel: (self.columns as f32 * self.cell_size.0) as u16,
+            ws_ypixel: (self.rows as f32 * self.cell_size.1) as u16,
+        }
+    }
+
+    /// Reallocates the screen to exactly `columns` x `rows` cells, when the screen
+    /// gets shorter the lines above the cursor are dropped first so it stays visible
+    fn resize_cells(&mut self, columns: u32, rows: u32) {
+        if columns == self.columns && rows == self.rows {
+            return;
+        }
+
+        // The primary screen is always rewrapped, even while hidden, programs on
+        // the alternate screen redraw themselves after a resize
+        if self.alternate_screen_active {
+            self.reflow_hidden_primary(columns, rows);
+        }
 
-        let winsize_ptr: *const nix::pty::Winsize = &winsize;
-        unsafe {
-            // Maybe i can handle this different from a panic?
-            tiocswinsz(self.fd, winsize_ptr as std::os::raw::c_int)
-                .unwrap_or_else(|e| panic!("Failed to resize the terminal, error: {e:?}"));
+        if columns != self.columns && !self.alternate_screen_active {
+            self.reflow_cells(columns, rows);
+        } else if rows < self.rows {
+            let overflow = (self.cursor.1 + 1).saturating_sub(rows) as usize;
+            for line in self.cells.drain(..overflow) {
+                if !self.alternate_screen_active {
+                    self.history.push(line);
+                }
+            }
+            self.cells.truncate(rows as usize);
+            self.cursor.1 -= overflow as u32;
+        }
+
+        for row in self.cells.iter_mut().chain(self.inactive_cells.iter_mut()) {
+            row.resize(columns as usize, TerminalCell::default());
         }
 
-        self.rows = rows as u32;
-        self.columns = columns as u32;
+        self.cells.resize(rows as usize, blank_row(columns));
+        self.inactive_cells
+            .resize(rows as usize, blank_row(columns));
+
+        // The stops set by the program are kept, new columns get the default ones
+        self.tab_stops.truncate(columns as usize);
+        self.tab_stops
+            .extend(default_tab_stops(self.columns.min(columns)..columns));
+
+        self.columns = columns;
+        self.rows = rows;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.left_margin = 0;
+        self.right_margin = columns - 1;
+        self.wrap_pending = false;
+        self.clamp_cursor();
     }
-}
 
-fn horizontal_absolute_char_write(
-    line: &mut Vec<TerminalCell>,
-    style: TerminalStyle,
-    x: usize,
-    c: char,
-) {
-    for _ in line.len()..x + 1 {
-        line.push(TerminalCell {
-            content: ' ',
-            style: TerminalStyle::default(),
-        });
+    /// Rewraps the history and the screen at `columns`, the screen ends up with at
+    /// most `rows` rows and the cursor stays on the same character
+    fn reflow_cells(&mut self, columns: u32, rows: u32) {
+        let history_len = self.history.len();
+        let mut lines = self.history.take_lines();
+
+        // The blank rows below the cursor only fill the screen, they are added back
+        let screen_end = self
+            .cells
+            .iter()
+            .rposition(|row| row.iter().any(|cell| !cell.is_blank()))
+            .map_or(0, |i| i + 1)
+            .max(self.cursor.1 as usize + 1);
+        lines.extend(self.cells.drain(..screen_end));
+
+        let cursor = (
+            self.cursor.0 as usize + self.wrap_pending as usize,
+            history_len + self.cursor.1 as usize,
+        );
+        let (mut lines, (x, y)) = reflow::reflow(lines, columns as usize, cursor);
+
+        let start = lines.len().saturating_sub(rows as usize).min(y);
+        let mut screen = lines.split_off(start);
+        screen.truncate(rows as usize);
+        for line in lines {
+            self.history.push(line);
+        }
+
+        self.cells = screen;
+        self.cursor = TerminalCursor(x as u32, (y - start) as u32);
+        self.display_offset = self.display_offset.min(self.history.len());
     }
 
-    if let Some(cell) = line.get_mut(x) {
-        cell.content = c;
-        cell.style = style;
-        return;
+    /// Rewraps the primary screen while the alternate one is displayed, the cursor
+    /// saved on the primary screen follows its character
+    fn reflow_hidden_primary(&mut self, columns: u32, rows: u32) {
+        let alternate_cursor = (self.cursor, self.wrap_pending);
+        std::mem::swap(&mut self.cells, &mut self.inactive_cells);
+        if let Some(saved) = &self.inactive_saved_cursor {
+            (self.cursor, self.wrap_pending) = (saved.position, saved.wrap_pending);
+        }
+
+        self.reflow_cells(columns, rows);
+
+        if let Some(saved) = &mut self.inactive_saved_cursor {
+            (saved.position, saved.wrap_pending) = (self.cursor, false);
+        }
+        std::mem::swap(&mut self.cells, &mut self.inactive_cells);
+        (self.cursor, self.wrap_pending) = alternate_cursor;
     }
 
-    unreachable!()
-}
+    fn clamp_cursor(&mut self) {
+        self.cursor.0 = self.cursor.0.min(self.columns - 1);
+        self.cursor.1 = self.cursor.1.min(self.rows - 1);
 
-impl vte::Perform for TerminalGrid {
-    fn print(&mut self, c: char) {
-        if let Some(row) = self.cells.get_mut(self.cursor.1 as usize) {
-            horizontal_absolute_char_write(row, self.current_style, self.cursor.0 as usize, c);
-            self.cursor.move_right(1);
-            return;
+        if self.modes.contains(TerminalModes::ORIGIN) {
+            let ((left, right), (top, bottom)) = (self.horizontal_margins(), self.scroll_region());
+            self.cursor.0 = self.cursor.0.clamp(left, right);
+            self.cursor.1 = self.cursor.1.clamp(top, bottom);
         }
+    }
+
+    /// The top left corner cursor addressing is relative to, the corner of the
+    /// margins while origin mode is set
+    fn origin(&self) -> (u32, u32) {
+        if self.modes.contains(TerminalModes::ORIGIN) {
+            (self.horizontal_margins().0, self.scroll_top)
+        } else {
+            (0, 0)
+        }
+    }
+
+    /// The first and last rows moved by scrolling
+    fn scroll_region(&self) -> (u32, u32) {
+        (self.scroll_top, self.scroll_bottom)
+    }
 
-        for _ in self.cells.len()..self.cursor.1 as usize + 1 {
-            let row = Vec::with_capacity(self.columns as usize);
-            self.cells.push(row);
+    /// The first and last columns moved by scrolling and editing operations
+    fn horizontal_margins(&self) -> (u32, u32) {
+        if self.modes.contains(TerminalModes::LEFT_RIGHT_MARGIN) {
+            (self.left_margin, self.right_margin)
+        } else {
+            (0, self.columns - 1)
         }
+    }
 
-        if let Some(line) = self.cells.get_mut(self.cursor.1 as usize) {
-            horizontal_absolute_char_write(line, self.current_style, self.cursor.0 as usize, c);
-            self.cursor.move_right(1);
+    /// DECSTBM, the cursor goes home after the region changes
+    fn set_scroll_region(&mut self, top: u16, bottom: u16) {
+        let bottom = (bottom as u32).min(self.rows);
+        if top as u32 >= bottom {
             return;
         }
 
-        unreachable!()
+        self.scroll_top = top as u32 - 1;
+        self.scroll_bottom = bottom - 1;
+
+        let (x, y) = self.origin();
+        self.goto(x, y);
     }
 
-    fn csi_dispatch(
-        &mut self,
-        params: &vte::Params,
-        _intermediates: &[u8],
-        _ignore: bool,
-        action: char,
-    ) {
-        if action != 'm' {
-            log::info!("{} {:?}", action, params);
+    /// DECSLRM, the cursor goes home after the margins change
+    fn set_horizontal_margins(&mut self, left: u16, right: u16) {
+        let right = (right as u32).min(self.columns);
+        if left as u32 >= right {
+            return;
         }
 
-        let params = params.iter().flatten().copied().collect::<Vec<_>>();
-        let eight_bit_color_table = {
-            let mut table = HashMap::new();
-            fill_color_table(&mut table);
+        self.left_margin = left as u32 - 1;
+        self.right_margin = right - 1;
 
-            table
-        };
+        let (x, y) = self.origin();
+        self.goto(x, y);
+    }
 
-        // TODO: Refactor this, looks really bad and still are ansi codes without being handled properly
-        match action {
-            'A' | 'B' | 'C' | 'D' => {
-                let distance = *params.get(0).unwrap_or(&1) as u32;
-                match action {
-                    'A' => self.cursor.move_up(distance),
-                    'B' => self.cursor.move_down(distance),
-                    'C' => self.cursor.move_right(distance),
-                    'D' => self.cursor.move_left(distance),
-                    _ => (),
-                }
-            }
-            'E' => {
-                let value = *params.get(0).unwrap_or(&1) as u32;
-                self.cursor.move_to(0, self.cursor.1 + value)
-            }
-            'F' => {
-                let value = *params.get(0).unwrap_or(&1) as u32;
-                self.cursor.move_to(0, self.cursor.1 - value)
-            }
-            'G' => {
-                let value = *params.get(0).unwrap_or(&0) as u32;
-                self.cursor.move_to(value, self.cursor.1)
-            }
+    /// Relative cursor moves stop at the margins when the cursor starts inside them
+    fn move_cursor_up(&mut self, count: u32) {
+        let (top, _) = self.scroll_region();
+        let limit = if self.cursor.1 >= top { top } else { 0 };
+        self.cursor.1 = self.cursor.1.saturating_sub(count).max(limit);
+    }
 
-            'H' | 'f' => {
-                self.cursor.move_to(
-                    *params.get(0).unwrap_or(&0) as u32,
-                    *params.get(1).unwrap_or(&0) as u32,
-                );
-            }
-            'J' => {
-                let value = params.get(0).unwrap_or(&0);
-                match value {
-                    0 => {
-                        self.cells.truncate(self.cursor.1 as usize);
-                        if let Some(line) = self.cells.get_mut(self.cursor.1 as usize) {
-                            line.truncate(self.cursor.0 as usize);
-                        }
-                    }
-                    1 => {
-                        self.cells.drain(self.cursor.1 as usize..);
-                        if let Some(line) = self.cells.get_mut(self.cursor.1 as usize) {
-                            line.drain(self.cursor.0 as usize..);
-                        }
-                    }
-                    2 | 3 => self.cells.clear(),
-                    _ => (),
-                }
-            }
-            'K' => {
-                let value = params.get(0).unwrap_or(&0);
-                if let Some(line) = self.cells.get_mut(self.cursor.1 as usize) {
-                    match value {
-                        0 => {
-                            line.truncate(self.cursor.0 as usize);
-                        }
-                        1 => {
-                            line.drain(self.cursor.0 as usize..);
-                        }
-                        2 => line.clear(),
-                        _ => (),
-                    }
-                };
-            }
+    fn move_cursor_down(&mut self, count: u32) {
+        let (_, bottom) = self.scroll_region();
+        let limit = if self.cursor.1 <= bottom {
+            bottom
+        } else {
+            self.rows - 1
+        };
+        self.cursor.1 = (self.cursor.1 + count).min(limit);
+    }
 
-            'm' => {
-                let mut i = 0;
-                while i <= params.len() {
-                    let Some(param) = params.get(i) else {
-                        return;
-                    };
-                    self.current_style.foreground = match param {
-                        0 => TerminalColor::White,
-                        30 => TerminalColor::Black,
-                        31 => TerminalColor::Red,
-                        32 => TerminalColor::Green,
-                        33 => TerminalColor::Yellow,
-                        34 => TerminalColor::Blue,
-                        35 => TerminalColor::Magenta,
-                        36 => TerminalColor::Cyan,
-                        37 => TerminalColor::White,
-                        38 => {
-                            if i + 1 >= params.len() {
-                                i += 2;
-                                continue;
-                            }
-
-                            let color = if params[i + 1] == 2 {
-                                let r = *params.get(i + 2).unwrap_or(&0) as u8;
-                                let g = *params.get(i + 3).unwrap_or(&0) as u8;
-                                let b = *params.get(i + 4).unwrap_or(&0) as u8;
-
-                                TerminalColor::Rgb(r, g, b)
-                            } else if params[i + 1] == 5 {
-                                let color_index = *params.get(i + 2).unwrap_or(&0) as u8;
-                                match color_index {
-                                    // TODO: found a better way to handle this case to avoid repetition
-                                    code @ 0..16 => match code {
-                                        0 => TerminalColor::Black,
-                                        1 => TerminalColor::Red,
-                                        2 => TerminalColor::Green,
-                                        3 => TerminalColor::Yellow,
-                                        4 => TerminalColor::Blue,
-                                        5 => TerminalColor::Magenta,
-                                        6 => TerminalColor::Cyan,
-                                        7 => TerminalColor::White,
-                                        8 => TerminalColor::BrightBlack,
-                                        9 => TerminalColor::BrightRed,
-                                        10 => TerminalColor::BrightGreen,
-                                        12 => TerminalColor::BrightYellow,
-                                        13 => TerminalColor::BrightBlue,
-                                        14 => TerminalColor::BrightMagenta,
-                                        15 => TerminalColor::BrightCyan,
-                                        16 => TerminalColor::BrightWhite,
-                                        _ => {
-                                            unreachable!()
-                                        }
-                                    },
-                                    code @ 16..231 => eight_bit_color_table
-                                        .get(&code)
-                                        .cloned()
-                                        .unwrap_or(TerminalColor::White),
-                                    code @ 231..255 => {
-                                        let gray = ((code - 231) * 10 + 8) as u8;
-
-                                        TerminalColor::Rgb(gray, gray, gray)
-                                    }
-                                    u8::MAX => {
-                                        unreachable!()
-                                    }
-                                }
-                            } else {
-                                i += 2;
-                                continue;
-                            };
-
-                            i += 3;
-                            color
-                        }
-                        39 => TerminalColor::White,
-                        90 => TerminalColor::BrightBlack,
-                        91 => TerminalColor::BrightRed,
-                        92 => TerminalColor::BrightGreen,
-                        93 => TerminalColor::BrightYellow,
-                        94 => TerminalColor::BrightBlue,
-                        95 => TerminalColor::BrightMagenta,
-                        96 => TerminalColor::BrightCyan,
-                        97 => TerminalColor::BrightWhite,
-                        _ => {
-                            i += 1;
-                            continue;
-                        }
-                    };
-                    i += 1;
-                }
-            }
-            _ => (),
+    fn move_cursor_left(&mut self, count: u32) {
+        let (left, _) = self.horizontal_margins();
+        let limit = if self.cursor.0 >= left { left } else { 0 };
+        self.cursor.0 = self.cursor.0.saturating_sub(count).max(limit);
+    }
+
+    fn move_cursor_right(&mut self, count: u32) {
+        let (_, right) = self.horizontal_margins();
+        let limit = if self.cursor.0 <= right {
+            right
+        } else {
+            self.columns - 1
         };
+        self.cursor.0 = (self.cursor.0 + count).min(limit);
     }
 
-    fn execute(&mut self, byte: u8) {
-        match byte {
-            0x08 => {
-                if self.cursor.0 > 0 {
-                    self.cursor.move_left(1);
-                } else if self.cursor.1 > 0 {
-                    self.cursor.move_to(self.columns - 1, self.cursor.1 - 1);
-                }
+    /// RI, like a line feed but upwards
+    fn reverse_index(&mut self) {
+        let (top, _) = self.scroll_region();
+
+        if self.cursor.1 == top {
+            self.scroll_down(1);
+        } else {
+            self.cursor.move_up(1);
+        }
+        self.wrap_pending = false;
+    }
+
+    /// Moves the cursor to an absolute position, clamped to the screen
+    fn goto(&mut self, x: u32, y: u32) {
+        self.cursor.move_to(x, y);
+        self.wrap_pending = false;
+        self.clamp_cursor();
+    }
+
+    /// LF, VT, FF and IND, a wrap pending on the last column is cancelled like in
+    /// xterm so the next character overwrites it on the new line
+    fn line_feed(&mut self) {
+        let (_, bottom) = self.scroll_region();
+        self.wrap_pending = false;
+
+        if self.cursor.1 == bottom {
+            self.scroll_up(1);
+        } else if self.cursor.1 + 1 < self.rows {
+            self.cursor.move_down(1);
+        }
+    }
+
+    fn carriage_return(&mut self) {
+        let (left, _) = self.horizontal_margins();
+        let x = if self.cursor.0 >= left { left } else { 0 };
+
+        self.cursor.move_to(x, self.cursor.1);
+        self.wrap_pending = false;
+    }
+
+    /// Scrolls the scroll region up, used by LF, IND and SU which are the only
+    /// ones saving lines in the history
+    fn scroll_up(&mut self, count: u32) {
+        let (top, bottom) = self.scroll_region();
+        self.scroll_lines_up(top, bottom, count, true);
+    }
+
+    fn scroll_down(&mut self, count: u32) {
+        let (top, bottom) = self.scroll_region();
+        self.scroll_lines_down(top, bottom, count);
+    }
+
+    /// Moves the lines between `top` and `bottom` `count` lines up, with `save` the
+    /// lines leaving the top of the screen are kept in the history (only on the
+    /// primary screen) and blank lines are inserted at the bottom
+    fn scroll_lines_up(&mut self, top: u32, bottom: u32, count: u32, save: bool) {
+        let count = count.min(bottom + 1 - top) as usize;
+        if self.has_horizontal_margins() {
+            for row in top as usize..=bottom as usize {
+                self.copy_margin_cells(row + count, row, bottom as usize);
             }
-            0x0A => {
-                if let Some(row) = self.cells.last_mut() {
-                    self.cursor.move_down(1);
-                    row.push(TerminalCell {
-                        content: '\n',
-                        style: self.current_style,
-                    });
-                }
+            return;
+        }
+
+        let blank = self.blank_row();
+        let save_lines = save && top == 0 && !self.alternate_screen_active;
+
+        let region = &mut self.cells[top as usize..=bottom as usize];
+        for line in region[..count].iter_mut() {
+            let line = std::mem::replace(line, blank.clone());
+            if save_lines {
+                self.history.push(line);
             }
-            0x0D => {
-                self.cursor.move_to(0, self.cursor.1);
+        }
+        region.rotate_left(count);
+
+        // Keep the view still when the user is reading the history
+        if save_lines && self.display_offset != 0 {
+            self.scroll_display(count as i32);
+        }
+    }
+
+    /// Moves the lines between `top` and `bottom` `count` lines down, the lines
+    /// pushed past `bottom` are discarded and blank lines are inserted at `top`
+    fn scroll_lines_down(&mut self, top: u32, bottom: u32, count: u32) {
+        let count = count.min(bottom + 1 - top) as usize;
+        if self.has_horizontal_margins() {
+            for row in (top as usize..=bottom as usize).rev() {
+                let source = row
+                    .checked_sub(count)
+                    .filter(|source| *source >= top as usize);
+                self.copy_margin_cells(source.unwrap_or(usize::MAX), row, bottom as usize);
             }
-            _ => (),
+            return;
         }
+
+        let blank = self.blank_row();
+
+        let region = &mut self.cells[top as usize..=bottom as usize];
+        region.rotate_right(count);
+        region[..count].fill(blank);
     }
-}
 
-#[derive(Debug, Default, Clone, Copy)]
-pub struct TerminalCell {
-    pub style: TerminalStyle,
-    pub content: char,
-}
+    fn has_horizontal_margins(&self) -> bool {
+        self.horizontal_margins() != (0, self.columns - 1)
+    }
 
-#[derive(Debug, Default)]
-struct TerminalCursor(u32, u32);
+    /// Copies the cells between the horizontal margins of the `source` row to the
+    /// `target` row, a `source` past `last_row` fills the target with blanks
+    fn copy_margin_cells(&mut self, source: usize, target: usize, last_row: usize) {
+        let (left, right) = self.horizontal_margins();
+        let columns = left as usize..=right as usize;
 
-impl TerminalCursor {
-    fn move_up(&mut self, y: u32) {
-        if self.1 >= y {
-            self.1 = self.1 - y;
-        } else {
-            self.1 = 0;
+        if source > last_row {
+            let blank = self.blank_cell();
+            self.cells[target][columns].fill(blank);
+            return;
         }
+
+        let source_cells = self.cells[source][columns.clone()].to_vec();
+        self.cells[target][columns].copy_from_slice(&source_cells);
     }
 
-    fn move_down(&mut self, y: u32) {
-        self.1 = self.1 + y
+    /// IL and DL, only have effect while the cursor is inside the margins
+    fn insert_lines(&mut self, count: u32, insert: bool) {
+        let ((top, bottom), (left, right)) = (self.scroll_region(), self.horizontal_margins());
+        let (x, y) = (self.cursor.0, self.cursor.1);
+        if y < top || y > bottom || x < left || x > right {
+            return;
+        }
+
+        if insert {
+            self.scroll_lines_down(y, bottom, count);
+        } else {
+            self.scroll_lines_up(y, bottom, count, false);
+        }
+        self.carriage_return();
     }
 
-    fn move_left(&mut self, x: u32) {
-        if self.0 >= x {
-            self.0 = self.0 - x;
+    /// The last column edited by ICH and DCH, the right margin unless the cursor
+    /// is past it
+    fn right_edit_column(&self) -> usize {
+        let (_, right) = self.horizontal_margins();
+        if self.cursor.0 <= right {
+            right as usize
         } else {
-            self.0 = 0;
+            self.columns as usize - 1
         }
     }
 
-    fn move_right(&mut self, x: u32) {
-        self.0 = self.0 + x
+    /// ICH, shifts the rest of the line to the right, the cells pushed past the
+    /// right margin are lost. Wide characters only move whole, one cut by the
+    /// cursor or by the margin is erased
+    fn insert_chars(&mut self, count: u32) {
+        let blank = self.blank_cell();
+        let (x, y, right) = (
+            self.cursor.0 as usize,
+            self.cursor.1 as usize,
+            self.right_edit_column(),
+        );
+
+        if self.cells[y][x].flags.contains(CellFlags::WIDE_SPACER) {
+            self.split_wide_char(x, y);
+            self.cells[y][x] = blank;
+        }
+        if self.cells[y][right].flags.contains(CellFlags::WIDE) {
+            self.split_wide_char(right, y);
+        }
+
+        let line = &mut self.cells[y][x..=right];
+        let count = (count as usize).min(line.len());
+
+        line.rotate_right(count);
+        line[..count].fill(blank);
+
+        // The second half of a wide character shifted into the last column was
+        // pushed out
+        if let Some(last) = line.last_mut() {
+            if last.flags.contains(CellFlags::WIDE) {
+                *last = blank;
+            }
+        }
+        self.wrap_pending = false;
     }
 
-    fn move_to(&mut self, x: u32, y: u32) {
-        self.0 = x;
-        self.1 = y;
+    /// DCH, shifts the rest of the line to the left filling the end with blanks.
+    /// Wide characters only move whole, one cut by the deleted cells or by the
+    /// margin is erased
+    fn delete_chars(&mut self, count: u32) {
+        let blank = self.blank_cell();
+        let (x, y, right) = (
+            self.cursor.0 as usize,
+            self.cursor.1 as usize,
+            self.right_edit_column(),
+        );
+        let count = (count as usize).min(right + 1 - x);
+
+        self.split_wide_char(x, y);
+        if count > 0 {
+            self.split_wide_char(x + count - 1, y);
+        }
+        if self.cells[y][right].flags.contains(CellFlags::WIDE) {
+            self.split_wide_char(right, y);
+            self.cells[y][right] = blank;
+        }
+
+        let line = &mut self.cells[y][x..=right];
+        line.rotate_left(count);
+        let len = line.len();
+        line[len - count..].fill(blank);
+        self.wrap_pending = false;
     }
 
-    fn get_pixel_coords(&self, width: f32, height: f32) -> (f32, f32) {
-        (width * self.0 as f32, height * self.1 as f32)
+    /// The cell left behind by erase operations, it keeps the current background
+    /// color (background color erase) like xterm does
+    fn blank_cell(&self) -> TerminalCell {
+        TerminalCell {
+            content: Grapheme::new(' '),
+            flags: CellFlags::empty(),
+            style: TerminalStyle {
+                background: self.current_style.background,
+                ..Default::default()
+            },
+        }
     }
-}
 
-#[derive(Debug, Clone, Copy)]
-pub struct TerminalStyle {
-    pub foreground: TerminalColor,
-}
+    fn blank_row(&self) -> Vec<TerminalCell> {
+        vec![self.blank_cell(); self.columns as usize]
+    }
 
-impl Default for TerminalStyle {
-    fn default() -> Self {
-        Self {
-            foreground: TerminalColor::White,
+    /// Moves the cursor forward `count` tab stops, stopping at the last column
+    fn forward_tab(&mut self, count: u32) {
+        for _ in 0..count {
+            let next = (self.cursor.0 + 1..self.columns).find(|c| self.tab_stops[*c as usize]);
+            self.cursor.0 = next.unwrap_or(self.columns - 1);
         }
+        self.wrap_pending = false;
     }
-}
 
-#[derive(Debug, Clone, Copy)]
-pub enum TerminalColor {
-    Black,
-    Red,
-    Green,
-    Blue,
-    Yellow,
-    Cyan,
-    Magenta,
-    White,
-    BrightBlack,
-    BrightRed,
-    BrightGreen,
-    BrightBlue,
-    BrightYellow,
-    BrightCyan,
-    BrightMagenta,
-    BrightWhite,
-    Rgb(u8, u8, u8),
-}
+    /// Moves the cursor backward `count` tab stops, stopping at the first column
+    fn backward_tab(&mut self, count: u32) {
+        for _ in 0..count {
+            let previous = (0..self.cursor.0).rfind(|c| self.tab_stops[*c as usize]);
+            self.cursor.0 = previous.unwrap_or(0);
+        }
+        self.wrap_pending = false;
+    }
+
+    fn clear_tab_stops(&mut self, mode: u16) {
+        match mode {
+            0 => self.tab_stops[self.cursor.0 as usize] = false,
+            3 => self.tab_stops.fill(false),
+            _ => (),
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            position: self.cursor,
+            style: self.current_style,
+            origin_mode: self.modes.contains(TerminalModes::ORIGIN),
+            wrap_pending: self.wrap_pending,
+            charsets: self.charsets,
+        });
+    }
+
+    /// Restores the state saved by [`Self::save_cursor`], without a saved state
+    /// the cursor goes home and the style is reset like xterm does
+    fn restore_cursor(&mut self) {
+        let saved = self.saved_cursor.unwrap_or_default();
+
+        self.cursor = saved.position;
+        self.current_style = saved.style;
+        self.modes.set(TerminalModes::ORIGIN, saved.origin_mode);
+        self.charsets = saved.charsets;
+        self.clamp_cursor();
+        self.wrap_pending = saved.wrap_pending;
+    }
 
-fn fill_color_table(table: &mut HashMap<u8, TerminalColor>) {
-    // This is basically copied from wikipedia, seems like gives different results than other terminals
-    // i should check this out later
-    for red in 0..6 {
-        for green in 0..6 {
-            for blue in 0..6 {
-                let code = 16 + (red * 36) + (green * 6) + blue;
-                let r = if red == 0 { 0 } else { red * 40 + 55 };
-                let g = if green == 0 { 0 } else { green * 40 + 55 };
-                let b = if blue == 0 { 0 } else { blue * 40 + 55 };
+    /// Writes an already translated character at the cursor and advances it
+    fn put_char(&mut self, c: char) {
+        self.finish_cluster();
 
-                table.insert(code, TerminalColor::Rgb(r, g, b));
+        // Zero width characters have no cell of their own
+        let width = match c.width() {
+            Some(0) | None => return,
+            Some(width) => width as u32,
+        };
+
+        if self.wrap_pending {
+            self.soft_wrap();
+        }
+
+        let right = self.print_boundary();
+
+        // A wide character that doesn't fit before the right margin goes to the next
+        // line, leaving the last column empty
+        if width == 2 && self.cursor.0 >= right {
+            if right == 0 {
+                return;
+            }
+
+            if self.modes.contains(TerminalModes::AUTO_WRAP) {
+                let (x, y) = (self.cursor.0 as usize, self.cursor.1 as usize);
+                self.split_wide_char(x, y);
+                self.cells[y][x] = TerminalCell {
+                    flags: CellFlags::LEADING_WIDE_SPACER,
+                    ..self.blank_cell()
+                };
+                self.soft_wrap();
+            } else {
+                self.cursor.0 = right - 1;
             }
         }
+
+        let (x, y) = (self.cursor.0 as usize, self.cursor.1 as usize);
+        self.split_wide_char(x, y);
+        if width == 2 {
+            self.split_wide_char(x + 1, y);
+        }
+
+        let style = self.current_style;
+        let row = &mut self.cells[y];
+        if let Some(cell) = row.get_mut(x) {
+            let flags = if width == 2 {
+                CellFlags::WIDE
+            } else {
+                CellFlags::empty()
+            };
+            let flags = flags | (cell.flags & CellFlags::WRAPLINE);
+            *cell = TerminalCell {
+                style,
+                content: Grapheme::new(c),
+                flags,
+            };
+        }
+        if let Some(cell) = row.get_mut(x + 1).filter(|_| width == 2) {
+            *cell = TerminalCell {
+                style,
+                content: Grapheme::new(' '),
+                flags: CellFlags::WIDE_SPACER,
+            };
+        }
+        self.last_printed = Some(c);
+
+        let last = self.cursor.0 + width - 1;
+        if last < self.print_boundary() {
+            self.cursor.move_right(width);
+        } else {
+            self.cursor.0 = last.min(self.columns - 1);
+            self.wrap_pending = self.modes.contains(TerminalModes::AUTO_WRAP);
+        }
+    }
+
+    /// Appends `c` to the cluster of the last printed cell when it continues it,
+    /// like a combining accent or the parts of a ZWJ sequence
+    fn extend_previous_cell(&mut self, c: char) -> bool {
+        if c.is_ascii() {
+            return false;
+        }
+
+        let Some(position) = self.previous_cell() else {
+            return false;
+        };
+
+        let mut cluster = match self.cluster.take() {
+            Some(cluster) if cluster.position == position => cluster,
+            pending => {
+                self.cluster = pending;
+                self.finish_cluster();
+                let (x, y) = position;
+                PendingCluster {
+                    position,
+                    text: self.cells[y][x].content.to_string(),
+                }
+            }
+        };
+
+        cluster.text.push(c);
+        let joined = cluster.text.graphemes(true).nth(1).is_none();
+        // Characters past the limit still belong to the cluster, they're dropped
+        if !joined || cluster.text.chars().count() > MAX_CLUSTER_CHARS {
+            cluster.text.pop();
+        }
+
+        self.cluster = Some(cluster);
+        joined
+    }
+
+    /// Interns the pending cluster into its cell, called before anything else
+    /// can change the grid
+    fn finish_cluster(&mut self) {
+        let Some(PendingCluster {
+            position: (x, y),
+            text,
+        }) = self.cluster.take()
+        else {
+            return;
+        };
+
+        if let Some(cell) = self.cells.get_mut(y).and_then(|row| row.get_mut(x)) {
+            cell.content = Grapheme::intern(&text);
+        }
+    }
+
+    /// The cell the cursor just moved past, the leading cell of a wide character
+    fn previous_cell(&self) -> Option<(usize, usize)> {
+        let (x, y) = (self.cursor.0 as usize, self.cursor.1 as usize);
+        let x = if self.wrap_pending {
+            x
+        } else {
+            x.checked_sub(1)?
+        };
+
+        if self.cells[y][x].flags.contains(CellFlags::WIDE_SPACER) {
+            return x.checked_sub(1).map(|x| (x, y));
+        }
+
+        Some((x, y))
+    }
+
+    /// Moves to the start of the next line after printing reached the right margin,
+    /// the row is marked as continuing on the next one when the margin is the
+    /// edge of the screen so it can be reflowed
+    fn soft_wrap(&mut self) {
+        let (x, y) = (self.cursor.0 as usize, self.cursor.1 as usize);
+        if x + 1 == self.columns as usize {
+            self.cells[y][x].flags.insert(CellFlags::WRAPLINE);
+        }
+
+        self.carriage_return();
+        self.line_feed();
+    }
+
+    /// The last column printing can reach, the right margin unless the cursor is
+    /// already past it
+    fn print_boundary(&self) -> u32 {
+        let (_, right) = self.horizontal_margins();
+        if self.cursor.0 <= right {
+            right
+        } else {
+            self.columns - 1
+        }
+    }
+
+    /// Blanks the other half of the wide character at the position, so overwriting
+    /// one half of it doesn't leave the other half behind
+    fn split_wide_char(&mut self, x: usize, y: usize) {
+        let blank = self.blank_cell();
+        let Some(row) = self.cells.get_mut(y) else {
+            return;
+        };
+        let Some(flags) = row.get(x).map(|cell| cell.flags) else {
+            return;
+        };
+
+        if flags.contains(CellFlags::WIDE) {
+            if let Some(cell) = row.get_mut(x + 1) {
+                *cell = blank;
+            }
+        } else if flags.contains(CellFlags::WIDE_SPACER) && x > 0 {
+            row[x - 1] = blank;
+        }
+    }
+
+    fn designate_charset(&mut self, intermediate: u8, designator: u8) {
+        let (Some(slot), Some(charset)) = (
+            Charsets::slot(intermediate),
+            Charset::from_designator(designator),
+        ) else {
+            log::info!("Unsupported charset {}", designator as char);
+            return;
+        };
+
+        self.charsets.designate(slot, charset);
+    }
+
+    /// RIS, everything but the size of the grid goes back to its initial state
+    fn full_reset(&mut self) {
+        let mut grid = TerminalGrid::new(self.history.limit(), self.responses.clone());
+        grid.resize_cells(self.columns, self.rows);
+        grid.cell_size = self.cell_size;
+        grid.width = self.width;
+        grid.height = self.height;
+        grid.title = self.title.take();
+
+        *self = grid;
+    }
+
+    /// DECSTR, resets the modes, margins and style without touching the screen
+    fn soft_reset(&mut self) {
+        let reverse_video = self.modes & TerminalModes::REVERSE_VIDEO;
+        self.modes = TerminalModes::default() | reverse_video;
+
+        self.scroll_top = 0;
+        self.scroll_bottom = self.rows - 1;
+        self.left_margin = 0;
+        self.right_margin = self.columns - 1;
+        self.current_style = TerminalStyle::default();
+        self.charsets = Charsets::default();
+        self.saved_cursor = None;
+        self.wrap_pending = false;
+    }
+
+    fn enter_alternate_screen(&mut self) {
+        if self.alternate_screen_active {
+            return;
+        }
+
+        std::mem::swap(&mut self.cells, &mut self.inactive_cells);
+        std::mem::swap(&mut self.saved_cursor, &mut self.inactive_saved_cursor);
+        self.alternate_screen_active = true;
+        self.display_offset = 0;
+    }
+
+    fn leave_alternate_screen(&mut self) {
+        if !self.alternate_screen_active {
+            return;
+        }
+
+        std::mem::swap(&mut self.cells, &mut self.inactive_cells);
+        std::mem::swap(&mut self.saved_cursor, &mut self.inactive_saved_cursor);
+        self.alternate_screen_active = false;
+    }
+
+    fn set_private_mode(&mut self, mode: u16, enabled: bool) {
+        if let Some(flag) = TerminalModes::from_private(mode) {
+            self.modes.set(flag, enabled);
+
+            match flag {
+                TerminalModes::ORIGIN => {
+                    let (x, y) = self.origin();
+                    self.goto(x, y);
+                }
+                TerminalModes::LEFT_RIGHT_MARGIN => {
+                    self.left_margin = 0;
+                    self.right_margin = self.columns - 1;
+                }
+                TerminalModes::AUTO_WRAP => self.wrap_pending = false,
+                _ => (),
+            }
+
+            return;
+        }
+
+        match (mode, enabled) {
+            (47, true) | (1047, true) => self.enter_alternate_screen(),
+            (47, false) => self.leave_alternate_screen(),
+            (1047, false) => {
+                if self.alternate_screen_active {
+                    self.erase_rows(0..self.rows);
+                }
+                self.leave_alternate_screen();
+            }
+            (1049, true) => {
+                self.save_cursor();
+                self.enter_alternate_screen();
+                self.erase_rows(0..self.rows);
+            }
+            (1049, false) => {
+                self.leave_alternate_screen();
+                self.restore_cursor();
+            }
+            _ => log::info!("Unhandled private mode {mode} (enabled: {enabled})"),
+        }
+    }
+
+    fn respond(&mut self, response: String) {
+        if self.responses.send(response).is_err() {
+            log::error!("Failed to send a response, the receiver was dropped");
+        }
+    }
+
+    /// Answers DSR queries, `private` is set for the DEC form (`CSI ? Ps n`)
+    fn device_status_report(&mut self, report: u16, private: bool) {
+        let prefix = if private { "?" } else { "" };
+
+        match report {
+            5 => self.respond("\x1b[0n".to_string()),
+            6 => {
+                let (left, top) = self.origin();
+                let (x, y) = (
+                    self.cursor.0.saturating_sub(left) + 1,
+                    self.cursor.1.saturating_sub(top) + 1,
+                );
+                self.respond(format!("\x1b[{prefix}{y};{x}R"));
+            }
+            _ => log::info!("Unhandled device status report {report}"),
+        }
+    }
+
+    fn set_title(&mut self, title: Option<String>) {
+        if title != self.title {
+            self.title = title;
+            self.title_changed = true;
+        }
+    }
+
+    /// Handles the title stack operations of XTWINOPS, `22` pushes and `23` pops
+    /// the icon name (`1`), the title (`2`) or both (`0`)
+    fn window_operation(&mut self, params: &[u16]) {
+        let which = *params.get(1).unwrap_or(&0);
+        let (icon, title) = (which != 2, which != 1);
+
+        match params.first() {
+            Some(22) => {
+                if self.title_stack.len() >= TITLE_STACK_LIMIT {
+                    self.title_stack.remove(0);
+                }
+
+                let entry = (
+                    icon.then(|| self.icon_name.clone()).flatten(),
+                    title.then(|| self.title.clone()).flatten(),
+                );
+                self.title_stack.push(entry);
+            }
+            Some(23) => {
+                let Some((saved_icon, saved_title)) = self.title_stack.pop() else {
+                    return;
+                };
+
+                if icon {
+                    self.icon_name = saved_icon;
+                }
+
+                if title {
+                    self.set_title(saved_title);
+                }
+            }
+            _ => log::info!("Unhandled window operation {params:?}"),
+        }
+    }
+
+    fn set_graphic_rendition(&mut self, params: &vte::Params) {
+        let style = &mut self.current_style;
+        if params.is_empty() {
+            *style = TerminalStyle::default();
+            return;
+        }
+
+        let mut params = params.iter();
+
+        while let Some(param) = params.next() {
+            match param {
+                [0] => *style = TerminalStyle::default(),
+                [1] => style.flags.insert(StyleFlags::BOLD),
+                [2] => style.flags.insert(StyleFlags::DIM),
+                [3] => style.flags.insert(StyleFlags::ITALIC),
+                [4, 0] => style.flags.remove(StyleFlags::UNDERLINE),
+                [4, ..] | [21] => style.flags.insert(StyleFlags::UNDERLINE),
+                [5] | [6] => style.flags.insert(StyleFlags::BLINK),
+                [7] => style.flags.insert(StyleFlags::INVERSE),
+                [8] => style.flags.insert(StyleFlags::HIDDEN),
+                [9] => style.flags.insert(StyleFlags::STRIKETHROUGH),
+                [22] => style.flags.remove(StyleFlags::BOLD | StyleFlags::DIM),
+                [23] => style.flags.remove(StyleFlags::ITALIC),
+                [24] => style.flags.remove(StyleFlags::UNDERLINE),
+                [25] => style.flags.remove(StyleFlags::BLINK),
+                [27] => style.flags.remove(StyleFlags::INVERSE),
+                [28] => style.flags.remove(StyleFlags::HIDDEN),
+                [29] => style.flags.remove(StyleFlags::STRIKETHROUGH),
+                [code @ 30..=37] => style.foreground = indexed_color((code - 30) as u8),
+                [38, subparams @ ..] => {
+                    if let Some(color) = parse_extended_color(subparams, &mut params) {
+                        style.foreground = color;
+                    }
+                }
+                [39] => style.foreground = TerminalColor::Default,
+                [code @ 40..=47] => style.background = indexed_color((code - 40) as u8),
+                [48, subparams @ ..] => {
+                    if let Some(color) = parse_extended_color(subparams, &mut params) {
+                        style.background = color;
+                    }
+                }
+                [49] => style.background = TerminalColor::Default,
+                [code @ 90..=97] => style.foreground = indexed_color((code - 90 + 8) as u8),
+                [code @ 100..=107] => style.background = indexed_color((code - 100 + 8) as u8),
+                _ => log::info!("Unhandled SGR parameter {param:?}"),
+            }
+        }
+    }
+
+    /// Erases the cells of `row` in `columns`, a wide character with only one half
+    /// in them is erased whole
+    fn erase_cells(&mut self, row: u32, columns: std::ops::Range<u32>) {
+        let blank = self.blank_cell();
+        let y = row as usize;
+        let Some(len) = self.cells.get(y).map(|line| line.len()) else {
+            return;
+        };
+
+        let end = (columns.end as usize).min(len);
+        let start = (columns.start as usize).min(end);
+        if start < end {
+            self.split_wide_char(start, y);
+            self.split_wide_char(end - 1, y);
+        }
+        self.cells[y][start..end].fill(blank);
+    }
+
+    fn erase_rows(&mut self, rows: std::ops::Range<u32>) {
+        for row in rows {
+            self.erase_cells(row, 0..self.columns);
+        }
+    }
+}
+
+fn default_tab_stops(columns: std::ops::Range<u32>) -> Vec<bool> {
+    columns.map(|column| column % TAB_WIDTH == 0).collect()
+}
+
+fn blank_row(columns: u32) -> Vec<TerminalCell> {
+    vec![TerminalCell::default(); columns as usize]
+}
+
+impl vte::Perform for TerminalGrid {
+    fn print(&mut self, c: char) {
+        let c = self.charsets.translate(c);
+        if !self.extend_previous_cell(c) {
+            self.put_char(c);
+        }
+    }
+
+    fn csi_dispatch(
+        &mut self,
+        params: &vte::Params,
+        intermediates: &[u8],
+        _ignore: bool,
+        action: char,
+    ) {
+        self.finish_cluster();
+
+        if action == 'm' && intermediates.is_empty() {
+            self.set_graphic_rendition(params);
+            return;
+        }
+
+        log::info!("{} {:?}", action, params);
+
+        let params = params.iter().flatten().copied().collect::<Vec<_>>();
+
+        match (intermediates, action) {
+            (b"?", 'h' | 'l') => {
+                for mode in params.iter() {
+                    self.set_private_mode(*mode, action == 'h');
+                }
+                return;
+            }
+            (b"?", 'n') => {
+                self.device_status_report(param(&params, 0, 0), true);
+                return;
+            }
+            (b">", 'c') => {
+                self.respond(format!("\x1b[>1;{};0c", version_number()));
+                return;
+            }
+            (b"!", 'p') => {
+                self.soft_reset();
+                return;
+            }
+            (b">", 'q') => {
+                self.respond(format!("\x1bP>|nart({})\x1b\\", env!("CARGO_PKG_VERSION")));
+                return;
+            }
+            ([], _) => (),
+            _ => return,
+        }
+
+        match action {
+            'A' => self.move_cursor_up(param(&params, 0, 1) as u32),
+            'B' | 'e' => self.move_cursor_down(param(&params, 0, 1) as u32),
+            'C' | 'a' => self.move_cursor_right(param(&params, 0, 1) as u32),
+            'D' => self.move_cursor_left(param(&params, 0, 1) as u32),
+            'E' => {
+                self.move_cursor_down(param(&params, 0, 1) as u32);
+                self.carriage_return();
+            }
+            'F' => {
+                self.move_cursor_up(param(&params, 0, 1) as u32);
+                self.carriage_return();
+            }
+            'G' | '`' => {
+                let (left, _) = self.origin();
+                self.cursor.0 = param(&params, 0, 1) as u32 - 1 + left;
+            }
+            'd' => {
+                let (_, top) = self.origin();
+                self.goto(self.cursor.0, param(&params, 0, 1) as u32 - 1 + top);
+            }
+            'H' | 'f' => {
+                let (row, column) = (param(&params, 0, 1) as u32, param(&params, 1, 1) as u32);
+                let (left, top) = self.origin();
+                self.goto(column - 1 + left, row - 1 + top);
+            }
+            'r' => {
+                let bottom = param(&params, 1, self.rows as u16);
+                self.set_scroll_region(param(&params, 0, 1), bottom);
+            }
+            's' if self.modes.contains(TerminalModes::LEFT_RIGHT_MARGIN) => {
+                let right = param(&params, 1, self.columns as u16);
+                self.set_horizontal_margins(param(&params, 0, 1), right);
+            }
+            's' => {
+                self.save_cursor();
+                return;
+            }
+            'u' => {
+                self.restore_cursor();
+                return;
+            }
+            'J' => {
+                let (x, y) = (self.cursor.0, self.cursor.1);
+                match param(&params, 0, 0) {
+                    0 => {
+                        self.erase_cells(y, x..self.columns);
+                        self.erase_rows(y + 1..self.rows);
+                    }
+                    1 => {
+                        self.erase_rows(0..y);
+                        self.erase_cells(y, 0..x + 1);
+                    }
+                    2 => self.erase_rows(0..self.rows),
+                    3 => {
+                        self.history.clear();
+                        self.display_offset = 0;
+                    }
+                    _ => (),
+                }
+            }
+            'K' => {
+                let (x, y) = (self.cursor.0, self.cursor.1);
+                match param(&params, 0, 0) {
+                    0 => self.erase_cells(y, x..self.columns),
+                    1 => self.erase_cells(y, 0..x + 1),
+                    2 => self.erase_cells(y, 0..self.columns),
+                    _ => (),
+                }
+            }
+            't' => self.window_operation(&params),
+            '@' => self.insert_chars(param(&params, 0, 1) as u32),
+            'P' => self.delete_chars(param(&params, 0, 1) as u32),
+            'X' => {
+                let (x, y) = (self.cursor.0, self.cursor.1);
+                self.erase_cells(y, x..x + param(&params, 0, 1) as u32);
+            }
+            'L' => self.insert_lines(param(&params, 0, 1) as u32, true),
+            'M' => self.insert_lines(param(&params, 0, 1) as u32, false),
+            'S' => self.scroll_up(param(&params, 0, 1) as u32),
+            // With more parameters this is the mouse highlight tracking of xterm
+            'T' if params.len() <= 1 => self.scroll_down(param(&params, 0, 1) as u32),
+            'b' => {
+                if let Some(c) = self.last_printed {
+                    for _ in 0..param(&params, 0, 1) {
+                        self.put_char(c);
+                    }
+                }
+                return;
+            }
+            'I' => self.forward_tab(param(&params, 0, 1) as u32),
+            'Z' => self.backward_tab(param(&params, 0, 1) as u32),
+            'g' => self.clear_tab_stops(param(&params, 0, 0)),
+            'n' => self.device_status_report(param(&params, 0, 0), false),
+            // Primary device attributes, a VT220 with ANSI colors
+            'c' if param(&params, 0, 0) == 0 => self.respond("\x1b[?62;22c".to_string()),
+            _ => (),
+        };
+
+        self.wrap_pending = false;
+        self.clamp_cursor();
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        self.finish_cluster();
+
+        let Some((command, text)) = params.split_first() else {
+            return;
+        };
+
+        // The text may contain `;`, which the parser takes as a separator
+        let text = String::from_utf8_lossy(&text.join(&b';')).into_owned();
+        match *command {
+            b"0" => {
+                self.icon_name = Some(text.clone());
+                self.set_title(Some(text));
+            }
+            b"1" => self.icon_name = Some(text),
+            b"2" => self.set_title(Some(text)),
+            _ => log::info!("Unhandled OSC {}", String::from_utf8_lossy(command)),
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        self.finish_cluster();
+
+        match byte {
+            // Without reverse wraparound, which isn't supported, BS stops at the
+            // left margin (or the first column outside of it) like in xterm
+            0x08 => {
+                let (left, _) = self.horizontal_margins();
+                let stop = if self.cursor.0 >= left { left } else { 0 };
+                if self.cursor.0 > stop {
+                    self.cursor.move_left(1);
+                }
+                self.wrap_pending = false;
+            }
+            0x09 => self.forward_tab(1),
+            0x0A..=0x0C => self.line_feed(),
+            0x0D => self.carriage_return(),
+            // SO and SI
+            0x0E => self.charsets.invoke(1),
+            0x0F => self.charsets.invoke(0),
+            _ => (),
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        self.finish_cluster();
+
+        match (intermediates, byte) {
+            // HTS
+            ([], b'H') => self.tab_stops[self.cursor.0 as usize] = true,
+            // IND
+            ([], b'D') => self.line_feed(),
+            // NEL
+            ([], b'E') => {
+                self.carriage_return();
+                self.line_feed();
+            }
+            // RI
+            ([], b'M') => self.reverse_index(),
+            // DECSC and DECRC
+            ([], b'7') => self.save_cursor(),
+            ([], b'8') => self.restore_cursor(),
+            // RIS
+            ([], b'c') => self.full_reset(),
+            // SCS, designates a set into G0-G3
+            ([intermediate @ (b'(' | b')' | b'*' | b'+')], _) => {
+                self.designate_charset(*intermediate, byte)
+            }
+            // SS2, SS3, LS2 and LS3
+            ([], b'N') => self.charsets.single_shift(2),
+            ([], b'O') => self.charsets.single_shift(3),
+            ([], b'n') => self.charsets.invoke(2),
+            ([], b'o') => self.charsets.invoke(3),
+            _ => log::info!("Unhandled ESC {intermediates:?} {}", byte as char),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TerminalCell {
+    pub style: TerminalStyle,
+    pub content: Grapheme,
+    pub flags: CellFlags,
+}
+
+impl TerminalCell {
+    pub fn is_blank(&self) -> bool {
+        self.content == Grapheme::new(' ')
+            && self.style == TerminalStyle::default()
+            && self.flags.is_empty()
+    }
+
+    /// The amount of columns the content of the cell is drawn across
+    pub fn width(&self) -> usize {
+        if self.flags.contains(CellFlags::WIDE) {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+impl Default for TerminalCell {
+    fn default() -> Self {
+        Self {
+            style: TerminalStyle::default(),
+            content: Grapheme::new(' '),
+            flags: CellFlags::empty(),
+        }
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct CellFlags: u8 {
+        /// The content is double width and continues in the next cell
+        const WIDE = 1;
+        /// The second half of a wide character, it has no content of its own
+        const WIDE_SPACER = 1 << 1;
+        /// The row was soft wrapped, set on its last cell when printing wrapped
+        const WRAPLINE = 1 << 2;
+        /// Fills the last column when a wide character didn't fit and wrapped
+        const LEADING_WIDE_SPACER = 1 << 3;
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct TerminalCursor(u32, u32);
+
+impl TerminalCursor {
+    fn move_up(&mut self, y: u32) {
+        if self.1 >= y {
+            self.1 -= y;
+        } else {
+            self.1 = 0;
+        }
+    }
+
+    fn move_down(&mut self, y: u32) {
+        self.1 += y
+    }
+
+    fn move_left(&mut self, x: u32) {
+        if self.0 >= x {
+            self.0 -= x;
+        } else {
+            self.0 = 0;
+        }
+    }
+
+    fn move_right(&mut self, x: u32) {
+        self.0 += x
+    }
+
+    fn move_to(&mut self, x: u32, y: u32) {
+        self.0 = x;
+        self.1 = y;
+    }
+
+    fn get_pixel_coords(&self, width: f32, height: f32) -> (f32, f32) {
+        (width * self.0 as f32, height * self.1 as f32)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct SavedCursor {
+    position: TerminalCursor,
+    style: TerminalStyle,
+    origin_mode: bool,
+    wrap_pending: bool,
+    charsets: Charsets,
+}
+
+/// A grapheme cluster still being printed, see [`TerminalGrid::finish_cluster`]
+struct PendingCluster {
+    /// Column and row of the cell the cluster goes in
+    position: (usize, usize),
+    text: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TerminalStyle {
+    pub foreground: TerminalColor,
+    pub background: TerminalColor,
+    pub flags: StyleFlags,
+}
+
+bitflags::bitflags! {
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct StyleFlags: u16 {
+        const BOLD = 1;
+        const DIM = 1 << 1;
+        const ITALIC = 1 << 2;
+        const UNDERLINE = 1 << 3;
+        const BLINK = 1 << 4;
+        const INVERSE = 1 << 5;
+        const HIDDEN = 1 << 6;
+        const STRIKETHROUGH = 1 << 7;
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum TerminalColor {
+    /// The configured foreground or background color, depending where it's used
+    #[default]
+    Default,
+    Black,
+    Red,
+    Green,
+    Blue,
+    Yellow,
+    Cyan,
+    Magenta,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightBlue,
+    BrightYellow,
+    BrightCyan,
+    BrightMagenta,
+    BrightWhite,
+    Rgb(u8, u8, u8),
+}
+
+/// Returns the parameter at `index`, a missing or zero parameter takes the default
+/// value as ECMA-48 specifies
+fn param(params: &[u16], index: usize, default: u16) -> u16 {
+    match params.get(index) {
+        None | Some(0) => default,
+        Some(value) => *value,
+    }
+}
+
+/// Encodes the crate version as a single number (`1.2.3` is `10203`) for the
+/// secondary device attributes report
+fn version_number() -> u32 {
+    env!("CARGO_PKG_VERSION")
+        .split('.')
+        .take(3)
+        .map(|part| part.parse::<u32>().unwrap_or(0))
+        .fold(0, |number, part| number * 100 + part)
+}
+
+/// Reads the color of a `38`/`48` SGR parameter, either from its subparameters
+/// (`38:2::r:g:b`, `38:5:n`) or from the parameters that follow it (`38;2;r;g;b`, `38;5;n`)
+fn parse_extended_color<'a>(
+    subparams: &[u16],
+    params: &mut impl Iterator<Item = &'a [u16]>,
+) -> Option<TerminalColor> {
+    let channel = |value: u16| value.min(u8::MAX as u16) as u8;
+
+    match subparams {
+        [] => (),
+        // The colon form may include a color space id before the components
+        [2, _, r, g, b, ..] | [2, r, g, b] => {
+            return Some(TerminalColor::Rgb(channel(*r), channel(*g), channel(*b)))
+        }
+        [5, index, ..] => return Some(indexed_color(channel(*index))),
+        _ => return None,
+    }
+
+    let mut next = || params.next().and_then(|param| param.first().copied());
+    match next()? {
+        2 => {
+            let (r, g, b) = (next()?, next()?, next()?);
+            Some(TerminalColor::Rgb(channel(r), channel(g), channel(b)))
+        }
+        5 => Some(indexed_color(channel(next()?))),
+        _ => None,
+    }
+}
+
+/// Maps an index of the 256 colors palette, the first 16 are the named colors,
+/// then a 6x6x6 color cube and at the end a grayscale ramp
+fn indexed_color(index: u8) -> TerminalColor {
+    match index {
+        0 => TerminalColor::Black,
+        1 => TerminalColor::Red,
+        2 => TerminalColor::Green,
+        3 => TerminalColor::Yellow,
+        4 => TerminalColor::Blue,
+        5 => TerminalColor::Magenta,
+        6 => TerminalColor::Cyan,
+        7 => TerminalColor::White,
+        8 => TerminalColor::BrightBlack,
+        9 => TerminalColor::BrightRed,
+        10 => TerminalColor::BrightGreen,
+        11 => TerminalColor::BrightYellow,
+        12 => TerminalColor::BrightBlue,
+        13 => TerminalColor::BrightMagenta,
+        14 => TerminalColor::BrightCyan,
+        15 => TerminalColor::BrightWhite,
+        16..=231 => {
+            let code = index - 16;
+            let level = |value: u8| if value == 0 { 0 } else { value * 40 + 55 };
+
+            TerminalColor::Rgb(level(code / 36), level((code / 6) % 6), level(code % 6))
+        }
+        232..=u8::MAX => {
+            let gray = (index - 232) * 10 + 8;
+
+            TerminalColor::Rgb(gray, gray, gray)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver};
+
+    use super::*;
+
+    fn grid(columns: u32, rows: u32) -> (TerminalGrid, Receiver<String>) {
+        let (sender, receiver) = mpsc::channel();
+        let mut grid = TerminalGrid::new(100, sender);
+        grid.resize_cells(columns, rows);
+
+        (grid, receiver)
+    }
+
+    fn row_text(grid: &TerminalGrid, row: usize) -> String {
+        let text = grid.cells[row]
+            .iter()
+            .filter(|c| !c.flags.contains(CellFlags::WIDE_SPACER))
+            .map(|c| c.content.to_string())
+            .collect::<String>();
+        text.trim_end().to_string()
+    }
+
+    fn cursor(grid: &TerminalGrid) -> (u32, u32) {
+        (grid.cursor.0, grid.cursor.1)
+    }
+
+    fn filled(columns: u32, rows: u32) -> TerminalGrid {
+        let (mut grid, _) = grid(columns, rows);
+        for row in 0..rows {
+            let line = "x".repeat(columns as usize);
+            grid.update(format!("\x1b[{};1H{line}", row + 1).as_bytes());
+        }
+
+        grid
+    }
+
+    #[test]
+    fn cup_is_one_based_row_then_column() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"\x1b[3;5H");
+        assert_eq!(cursor(&grid), (4, 2));
+
+        grid.update(b"\x1b[2;7f");
+        assert_eq!(cursor(&grid), (6, 1));
+    }
+
+    #[test]
+    fn cup_defaults_and_zero_go_home() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"\x1b[3;5H\x1b[H");
+        assert_eq!(cursor(&grid), (0, 0));
+
+        grid.update(b"\x1b[3;5H\x1b[0;0H");
+        assert_eq!(cursor(&grid), (0, 0));
+
+        grid.update(b"\x1b[4H");
+        assert_eq!(cursor(&grid), (0, 3));
+    }
+
+    #[test]
+    fn cup_clamps_to_the_grid() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"\x1b[99;99H");
+        assert_eq!(cursor(&grid), (9, 4));
+    }
+
+    #[test]
+    fn relative_moves_clamp_to_the_grid() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"\x1b[3;3H\x1b[10A");
+        assert_eq!(cursor(&grid), (2, 0));
+
+        grid.update(b"\x1b[10B");
+        assert_eq!(cursor(&grid), (2, 4));
+
+        grid.update(b"\x1b[20C");
+        assert_eq!(cursor(&grid), (9, 4));
+
+        grid.update(b"\x1b[20D");
+        assert_eq!(cursor(&grid), (0, 4));
+
+        grid.update(b"\x1b[0C");
+        assert_eq!(cursor(&grid), (1, 4));
+    }
+
+    #[test]
+    fn cnl_and_cpl_move_to_the_first_column() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"\x1b[2;5H\x1b[2E");
+        assert_eq!(cursor(&grid), (0, 3));
+
+        grid.update(b"\x1b[1;5H\x1b[3F");
+        assert_eq!(cursor(&grid), (0, 0));
+
+        grid.update(b"\x1b[5;5H\x1b[9E");
+        assert_eq!(cursor(&grid), (0, 4));
+    }
+
+    #[test]
+    fn cha_and_vpa_are_absolute() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"\x1b[2;2H\x1b[7G");
+        assert_eq!(cursor(&grid), (6, 1));
+
+        grid.update(b"\x1b[4d");
+        assert_eq!(cursor(&grid), (6, 3));
+
+        grid.update(b"\x1b[G");
+        assert_eq!(cursor(&grid), (0, 3));
+    }
+
+    #[test]
+    fn ed_below_erases_from_the_cursor() {
+        let mut grid = filled(5, 3);
+        grid.update(b"\x1b[2;3H\x1b[J");
+
+        assert_eq!(row_text(&grid, 0), "xxxxx");
+        assert_eq!(row_text(&grid, 1), "xx");
+        assert_eq!(row_text(&grid, 2), "");
+    }
+
+    #[test]
+    fn ed_above_erases_up_to_the_cursor() {
+        let mut grid = filled(5, 3);
+        grid.update(b"\x1b[2;3H\x1b[1J");
+
+        assert_eq!(row_text(&grid, 0), "");
+        assert_eq!(row_text(&grid, 1), "   xx");
+        assert_eq!(row_text(&grid, 2), "xxxxx");
+    }
+
+    #[test]
+    fn ed_all_erases_the_screen_and_keeps_the_cursor() {
+        let mut grid = filled(5, 3);
+        grid.update(b"\x1b[2;3H\x1b[2J");
+
+        assert!((0..3).all(|row| row_text(&grid, row).is_empty()));
+        assert_eq!(cursor(&grid), (2, 1));
+    }
+
+    #[test]
+    fn el_erases_the_right_side_of_the_line() {
+        let mut grid = filled(5, 3);
+        grid.update(b"\x1b[2;3H\x1b[K");
+
+        assert_eq!(row_text(&grid, 0), "xxxxx");
+        assert_eq!(row_text(&grid, 1), "xx");
+        assert_eq!(row_text(&grid, 2), "xxxxx");
+    }
+
+    #[test]
+    fn el_erases_the_left_side_of_the_line() {
+        let mut grid = filled(5, 3);
+        grid.update(b"\x1b[2;3H\x1b[1K");
+
+        assert_eq!(row_text(&grid, 1), "   xx");
+    }
+
+    #[test]
+    fn el_erases_the_whole_line() {
+        let mut grid = filled(5, 3);
+        grid.update(b"\x1b[2;3H\x1b[2K");
+
+        assert_eq!(row_text(&grid, 0), "xxxxx");
+        assert_eq!(row_text(&grid, 1), "");
+    }
+
+    #[test]
+    fn origin_mode_homes_the_cursor() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"\x1b[3;3H\x1b[?6h");
+        assert_eq!(cursor(&grid), (0, 0));
+
+        grid.update(b"\x1b[2;4H\x1b[?6l");
+        assert_eq!(cursor(&grid), (0, 0));
+    }
+
+    #[test]
+    fn cursor_position_report_is_one_based() {
+        let (mut grid, responses) = grid(10, 5);
+        grid.update(b"\x1b[3;7H\x1b[6n");
+
+        assert_eq!(responses.try_recv().unwrap(), "\x1b[3;7R");
+    }
+
+    #[test]
+    fn printing_past_the_last_column_wraps() {
+        let (mut grid, _) = grid(5, 3);
+        grid.update(b"abcde");
+        assert_eq!(cursor(&grid), (4, 0));
+
+        grid.update(b"f");
+        assert_eq!(row_text(&grid, 0), "abcde");
+        assert_eq!(row_text(&grid, 1), "f");
+        assert_eq!(cursor(&grid), (1, 1));
+    }
+
+    #[test]
+    fn line_feed_on_the_last_row_scrolls() {
+        let (mut grid, _) = grid(5, 3);
+        grid.update(b"one\r\ntwo\r\nthree\r\nfour");
+
+        assert_eq!(row_text(&grid, 0), "two");
+        assert_eq!(row_text(&grid, 2), "four");
+        assert_eq!(grid.history.len(), 1);
+        assert_eq!(cursor(&grid), (4, 2));
+    }
+
+    #[test]
+    fn line_feed_cancels_a_pending_wrap() {
+        let (mut grid, _) = grid(5, 3);
+        grid.update(b"abcde\nf");
+
+        assert_eq!(row_text(&grid, 0), "abcde");
+        assert_eq!(row_text(&grid, 1), "    f");
+        assert_eq!(cursor(&grid), (4, 1));
+        assert!(!grid.cells[0][4].flags.contains(CellFlags::WRAPLINE));
+        assert!(!grid.cells[1][4].flags.contains(CellFlags::WRAPLINE));
+    }
+
+    type Snapshot = (
+        Vec<Vec<(Grapheme, TerminalStyle)>>,
+        (u32, u32),
+        Option<String>,
+    );
+
+    fn snapshot(grid: &TerminalGrid) -> Snapshot {
+        let cells = grid
+            .cells
+            .iter()
+            .map(|row| row.iter().map(|c| (c.content, c.style)).collect())
+            .collect();
+
+        (cells, cursor(grid), grid.title.clone())
+    }
+
+    const SPLIT_INPUT: &[u8] = "h\u{e9}llo \u{4e16}\u{1f600}\x1b[1;31mred\x1b[0m\r\n\
+        \x1b]2;a title\x07\x1b[38:2::10:20:30mrgb\x1b[3;4Hend\x1b[?25l"
+        .as_bytes();
+
+    #[test]
+    fn input_split_at_every_offset_parses_the_same() {
+        let (mut expected, _) = grid(20, 5);
+        expected.update(SPLIT_INPUT);
+
+        for offset in 0..=SPLIT_INPUT.len() {
+            let (mut split, _) = grid(20, 5);
+            let (head, tail) = SPLIT_INPUT.split_at(offset);
+            split.update(head);
+            split.update(tail);
+
+            assert_eq!(snapshot(&split), snapshot(&expected), "split at {offset}");
+            assert_eq!(split.modes(), expected.modes(), "split at {offset}");
+        }
+    }
+
+    #[test]
+    fn input_fed_byte_by_byte_parses_the_same() {
+        let (mut expected, _) = grid(20, 5);
+        expected.update(SPLIT_INPUT);
+
+        let (mut split, _) = grid(20, 5);
+        for byte in SPLIT_INPUT {
+            split.update(std::slice::from_ref(byte));
+        }
+
+        assert_eq!(snapshot(&split), snapshot(&expected));
+    }
+
+    #[test]
+    fn tabs_stop_every_eight_columns_by_default() {
+        let (mut grid, _) = grid(20, 3);
+        grid.update(b"a\tb\tc\t");
+
+        assert_eq!(row_text(&grid, 0), "a       b       c");
+        assert_eq!(cursor(&grid), (19, 0));
+    }
+
+    #[test]
+    fn tab_stops_can_be_set_and_cleared() {
+        let (mut grid, _) = grid(20, 3);
+        grid.update(b"\x1b[3g\x1b[1;4H\x1bH\x1b[1;11H\x1bH\r\t");
+        assert_eq!(cursor(&grid), (3, 0));
+
+        grid.update(b"\x1b[I");
+        assert_eq!(cursor(&grid), (10, 0));
+
+        grid.update(b"\x1b[2Z");
+        assert_eq!(cursor(&grid), (0, 0));
+
+        grid.update(b"\x1b[1;4H\x1b[g\r\t");
+        assert_eq!(cursor(&grid), (10, 0));
+    }
+
+    #[test]
+    fn tab_stops_follow_the_grid_width() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b[1;4H\x1bH");
+        grid.resize_cells(20, 3);
+        grid.update(b"\r\t\t\t");
+
+        assert_eq!(cursor(&grid), (16, 0));
+    }
+
+    fn lines(columns: u32, rows: &[&str]) -> TerminalGrid {
+        let (mut grid, _) = grid(columns, rows.len() as u32);
+        for (row, text) in rows.iter().enumerate() {
+            grid.update(format!("\x1b[{};1H{text}", row + 1).as_bytes());
+        }
+
+        grid
+    }
+
+    #[test]
+    fn ich_shifts_the_line_right() {
+        let mut grid = lines(6, &["abcdef"]);
+        grid.update(b"\x1b[1;2H\x1b[2@");
+
+        assert_eq!(row_text(&grid, 0), "a  bcd");
+        assert_eq!(cursor(&grid), (1, 0));
+    }
+
+    #[test]
+    fn dch_shifts_the_line_left() {
+        let mut grid = lines(6, &["abcdef"]);
+        grid.update(b"\x1b[1;2H\x1b[2P");
+
+        assert_eq!(row_text(&grid, 0), "adef");
+    }
+
+    #[test]
+    fn ech_erases_without_shifting() {
+        let mut grid = lines(6, &["abcdef"]);
+        grid.update(b"\x1b[1;2H\x1b[3X");
+
+        assert_eq!(row_text(&grid, 0), "a   ef");
+        assert_eq!(cursor(&grid), (1, 0));
+    }
+
+    #[test]
+    fn il_and_dl_move_the_lines_below_the_cursor() {
+        let mut grid = lines(3, &["a", "b", "c", "d"]);
+        grid.update(b"\x1b[2;2H\x1b[L");
+
+        assert_eq!(row_text(&grid, 1), "");
+        assert_eq!(row_text(&grid, 2), "b");
+        assert_eq!(row_text(&grid, 3), "c");
+        assert_eq!(cursor(&grid), (0, 1));
+
+        grid.update(b"\x1b[2M");
+        assert_eq!(row_text(&grid, 0), "a");
+        assert_eq!(row_text(&grid, 1), "c");
+        assert_eq!(row_text(&grid, 2), "");
+
+        // Deleted lines are gone, even from the top of the screen
+        grid.update(b"\x1b[H\x1b[2M");
+        assert_eq!(row_text(&grid, 0), "");
+        assert_eq!(grid.history.len(), 0);
+    }
+
+    #[test]
+    fn su_and_sd_scroll_the_page() {
+        let mut grid = lines(3, &["a", "b", "c"]);
+        grid.update(b"\x1b[S");
+
+        assert_eq!(row_text(&grid, 0), "b");
+        assert_eq!(row_text(&grid, 2), "");
+        assert_eq!(grid.history.len(), 1);
+
+        grid.update(b"\x1b[2T");
+        assert_eq!(row_text(&grid, 0), "");
+        assert_eq!(row_text(&grid, 2), "b");
+    }
+
+    #[test]
+    fn rep_repeats_the_last_character() {
+        let (mut grid, _) = grid(10, 2);
+        grid.update(b"ab\x1b[3b");
+
+        assert_eq!(row_text(&grid, 0), "abbbb");
+        assert_eq!(cursor(&grid), (5, 0));
+    }
+
+    #[test]
+    fn erasing_keeps_the_current_background() {
+        let mut grid = lines(4, &["abcd", "efgh"]);
+        grid.update(b"\x1b[44m\x1b[1;3H\x1b[K\x1b[2;1H\x1b[P");
+
+        let blue = TerminalColor::Blue;
+        assert_eq!(grid.cells[0][1].style.background, TerminalColor::Default);
+        assert_eq!(grid.cells[0][2].style.background, blue);
+        assert_eq!(grid.cells[0][3].style.background, blue);
+        assert_eq!(grid.cells[1][3].style.background, blue);
+    }
+
+    #[test]
+    fn line_feed_scrolls_only_the_scroll_region() {
+        let mut grid = lines(3, &["a", "b", "c", "d", "e"]);
+        grid.update(b"\x1b[2;4r");
+        assert_eq!(cursor(&grid), (0, 0));
+
+        grid.update(b"\x1b[4;1H\nx");
+        assert_eq!(row_text(&grid, 0), "a");
+        assert_eq!(row_text(&grid, 1), "c");
+        assert_eq!(row_text(&grid, 2), "d");
+        assert_eq!(row_text(&grid, 3), "x");
+        assert_eq!(row_text(&grid, 4), "e");
+        assert_eq!(grid.history.len(), 0);
+    }
+
+    #[test]
+    fn reverse_index_at_the_top_margin_scrolls_down() {
+        let mut grid = lines(3, &["a", "b", "c", "d"]);
+        grid.update(b"\x1b[2;3r\x1b[2;1H\x1bM");
+
+        assert_eq!(row_text(&grid, 0), "a");
+        assert_eq!(row_text(&grid, 1), "");
+        assert_eq!(row_text(&grid, 2), "b");
+        assert_eq!(row_text(&grid, 3), "d");
+    }
+
+    #[test]
+    fn origin_mode_addresses_relative_to_the_scroll_region() {
+        let (mut grid, responses) = grid(10, 6);
+        grid.update(b"\x1b[3;5r\x1b[?6h");
+        assert_eq!(cursor(&grid), (0, 2));
+
+        grid.update(b"\x1b[2;3H\x1b[6n");
+        assert_eq!(cursor(&grid), (2, 3));
+        assert_eq!(responses.try_recv().unwrap(), "\x1b[2;3R");
+
+        grid.update(b"\x1b[9;1H");
+        assert_eq!(cursor(&grid), (0, 4));
+    }
+
+    #[test]
+    fn left_and_right_margins_limit_scrolling() {
+        let mut grid = lines(4, &["abcd", "efgh", "ijkl"]);
+        grid.update(b"\x1b[?69h\x1b[2;3s\x1b[3;2H\x1bD");
+
+        assert_eq!(row_text(&grid, 0), "afgd");
+        assert_eq!(row_text(&grid, 1), "ejkh");
+        assert_eq!(row_text(&grid, 2), "i  l");
+    }
+
+    #[test]
+    fn backspace_stops_at_the_left_margin() {
+        let (mut grid, responses) = grid(10, 5);
+        grid.update(b"\x1b[?69h\x1b[3;6s\x1b[?6h\x08\x1b[6n");
+
+        assert_eq!(cursor(&grid), (2, 0));
+        assert_eq!(responses.try_recv().unwrap(), "\x1b[1;1R");
+
+        // Outside of the margins it goes back to the first column
+        grid.update(b"\x1b[?6l\x1b[1;2H\x08\x08");
+        assert_eq!(cursor(&grid), (0, 0));
+    }
+
+    #[test]
+    fn nel_moves_to_the_start_of_the_next_line() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b[1;5H\x1bE");
+
+        assert_eq!(cursor(&grid), (0, 1));
+    }
+
+    #[test]
+    fn decsc_and_decrc_restore_the_cursor_state() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"\x1b[2;3r\x1b[?6h\x1b[2;4H\x1b[1;32m\x1b7");
+        grid.update(b"\x1b[?6l\x1b[0m\x1b[5;5H\x1b8");
+
+        assert_eq!(cursor(&grid), (3, 2));
+        assert_eq!(grid.current_style.foreground, TerminalColor::Green);
+        assert!(grid.modes().contains(TerminalModes::ORIGIN));
+    }
+
+    #[test]
+    fn scosc_saves_the_cursor_without_margins() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"\x1b[3;4H\x1b[s\x1b[H\x1b[u");
+
+        assert_eq!(cursor(&grid), (3, 2));
+    }
+
+    #[test]
+    fn alternate_screen_keeps_the_primary_saved_cursor() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"\x1b[2;2H\x1b[?1049h\x1b[4;4H\x1b7\x1b[H\x1b[?1049l");
+
+        assert_eq!(cursor(&grid), (1, 1));
+    }
+
+    #[test]
+    fn ris_resets_the_grid() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"text\x1b[31m\x1b[2;4r\x1b[?25l\x1b[?1049h\x1bc");
+
+        assert!((0..5).all(|row| row_text(&grid, row).is_empty()));
+        assert_eq!(cursor(&grid), (0, 0));
+        assert_eq!(grid.current_style, TerminalStyle::default());
+        assert_eq!(grid.modes(), TerminalModes::default());
+        assert_eq!(grid.scroll_region(), (0, 4));
+        assert!(!grid.alternate_screen_active);
+        assert_eq!((grid.columns, grid.rows), (10, 5));
+    }
+
+    #[test]
+    fn decstr_keeps_the_screen_contents() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"text\x1b[31m\x1b[2;4r\x1b[?25l\x1b[?6h\x1b[!p");
+
+        assert_eq!(row_text(&grid, 0), "text");
+        assert_eq!(grid.current_style, TerminalStyle::default());
+        assert_eq!(grid.modes(), TerminalModes::default());
+        assert_eq!(grid.scroll_region(), (0, 4));
+    }
+
+    #[test]
+    fn dec_special_graphics_draws_lines() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b(0lqqk\x1b(Bq");
+
+        assert_eq!(row_text(&grid, 0), "┌──┐q");
+    }
+
+    #[test]
+    fn shift_out_invokes_g1() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b)0x\x0ex\x0fx");
+
+        assert_eq!(row_text(&grid, 0), "x│x");
+    }
+
+    #[test]
+    fn decrc_restores_the_charsets() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b(0\x1b7\x1b(B\x1b[1;3Hq\x1b8q");
+
+        assert_eq!(row_text(&grid, 0), "─ q");
+    }
+
+    #[test]
+    fn wide_characters_take_two_cells() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update("a\u{4e16}b".as_bytes());
+
+        assert!(grid.cells[0][1].flags.contains(CellFlags::WIDE));
+        assert!(grid.cells[0][2].flags.contains(CellFlags::WIDE_SPACER));
+        assert_eq!(row_text(&grid, 0), "a\u{4e16}b");
+        assert_eq!(cursor(&grid), (4, 0));
+    }
+
+    #[test]
+    fn wide_character_in_the_last_column_wraps() {
+        let (mut grid, _) = grid(5, 3);
+        grid.update("abcd\u{1f600}".as_bytes());
+
+        assert_eq!(row_text(&grid, 0), "abcd");
+        assert_eq!(row_text(&grid, 1), "\u{1f600}");
+        assert_eq!(cursor(&grid), (2, 1));
+    }
+
+    #[test]
+    fn wide_character_filling_the_line_defers_the_wrap() {
+        let (mut grid, _) = grid(4, 3);
+        grid.update("ab\u{4e16}".as_bytes());
+
+        assert_eq!(cursor(&grid), (3, 0));
+        assert!(grid.wrap_pending);
+    }
+
+    #[test]
+    fn overwriting_half_of_a_wide_character_clears_it() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update("\u{4e16}\u{754c}\x1b[1;2Hx".as_bytes());
+
+        assert_eq!(row_text(&grid, 0), " x\u{754c}");
+        assert!(grid.cells[0][0].flags.is_empty());
+    }
+
+    fn has_orphan_halves(grid: &TerminalGrid, row: usize) -> bool {
+        let cells = &grid.cells[row];
+        cells.iter().enumerate().any(|(x, cell)| {
+            let spacer = |x: usize| {
+                cells
+                    .get(x)
+                    .is_some_and(|c| c.flags.contains(CellFlags::WIDE_SPACER))
+            };
+            let leader = x > 0 && cells[x - 1].flags.contains(CellFlags::WIDE);
+            (cell.flags.contains(CellFlags::WIDE) && !spacer(x + 1))
+                || (cell.flags.contains(CellFlags::WIDE_SPACER) && !leader)
+        })
+    }
+
+    #[test]
+    fn dch_erases_a_wide_character_it_cuts() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update("\u{4e16}x\x1b[1;1H\x1b[P".as_bytes());
+
+        assert_eq!(row_text(&grid, 0), " x");
+        assert!(!has_orphan_halves(&grid, 0));
+
+        grid.update("\x1b[1;1Ha\u{4e16}\u{754c}x\x1b[1;3H\x1b[2P".as_bytes());
+        assert_eq!(row_text(&grid, 0), "a  x");
+        assert!(!has_orphan_halves(&grid, 0));
+    }
+
+    #[test]
+    fn ich_erases_a_wide_character_it_cuts() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update("\u{4e16}x\x1b[1;2H\x1b[@".as_bytes());
+
+        assert_eq!(row_text(&grid, 0), "   x");
+        assert!(!has_orphan_halves(&grid, 0));
+    }
+
+    #[test]
+    fn ich_blanks_a_wide_character_pushed_into_the_last_column() {
+        let (mut grid, _) = grid(5, 3);
+        grid.update("ab\u{4e16}\x1b[1;1H\x1b[2@".as_bytes());
+
+        assert_eq!(row_text(&grid, 0), "  ab");
+        assert!(!has_orphan_halves(&grid, 0));
+    }
+
+    #[test]
+    fn erasing_half_of_a_wide_character_erases_it_whole() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update("a\u{4e16}b\x1b[1;3H\x1b[K".as_bytes());
+        assert_eq!(row_text(&grid, 0), "a");
+        assert!(!has_orphan_halves(&grid, 0));
+
+        grid.update("\x1b[2;1H\u{4e16}b\x1b[2;1H\x1b[X".as_bytes());
+        assert_eq!(row_text(&grid, 1), "  b");
+        assert!(!has_orphan_halves(&grid, 1));
+
+        grid.update("\x1b[3;1H\u{4e16}b\x1b[3;1H\x1b[1K".as_bytes());
+        assert_eq!(row_text(&grid, 2), "  b");
+        assert!(!has_orphan_halves(&grid, 2));
+    }
+
+    #[test]
+    fn combining_characters_join_the_previous_cell() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update("e\u{301}x".as_bytes());
+
+        assert_eq!(grid.cells[0][0].content.to_string(), "e\u{301}");
+        assert_eq!(row_text(&grid, 0), "e\u{301}x");
+        assert_eq!(cursor(&grid), (2, 0));
+    }
+
+    #[test]
+    fn zwj_sequences_stay_in_one_wide_cell() {
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        let (mut grid, _) = grid(10, 3);
+        grid.update(family.as_bytes());
+
+        assert_eq!(grid.cells[0][0].content.to_string(), family);
+        assert!(grid.cells[0][1].flags.contains(CellFlags::WIDE_SPACER));
+        assert_eq!(cursor(&grid), (2, 0));
+    }
+
+    #[test]
+    fn combining_character_after_a_pending_wrap_joins_the_last_column() {
+        let (mut grid, _) = grid(3, 3);
+        grid.update("abc\u{308}".as_bytes());
+
+        assert_eq!(row_text(&grid, 0), "abc\u{308}");
+        assert!(grid.wrap_pending);
+    }
+
+    #[test]
+    fn narrowing_rewraps_the_lines() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"abcdefgh\r\nxy");
+        grid.resize_cells(4, 2);
+
+        assert_eq!(grid.history.len(), 1);
+        assert_eq!(row_text(&grid, 0), "efgh");
+        assert_eq!(row_text(&grid, 1), "xy");
+        assert_eq!(cursor(&grid), (2, 1));
+    }
+
+    #[test]
+    fn widening_joins_soft_wrapped_rows() {
+        let (mut grid, _) = grid(4, 3);
+        grid.update(b"abcdefgh\r\nxy");
+        assert_eq!(row_text(&grid, 0), "abcd");
+
+        grid.resize_cells(10, 3);
+
+        assert_eq!(row_text(&grid, 0), "abcdefgh");
+        assert_eq!(row_text(&grid, 1), "xy");
+        assert_eq!(cursor(&grid), (2, 1));
+    }
+
+    #[test]
+    fn reflow_pulls_wrapped_lines_back_from_the_history() {
+        let (mut grid, _) = grid(4, 2);
+        grid.update(b"abcdefghij");
+        assert_eq!(grid.history.len(), 1);
+
+        grid.resize_cells(12, 2);
+
+        assert_eq!(grid.history.len(), 0);
+        assert_eq!(row_text(&grid, 0), "abcdefghij");
+        assert_eq!(cursor(&grid), (10, 0));
+    }
+
+    #[test]
+    fn hard_line_breaks_are_kept_when_widening() {
+        let (mut grid, _) = grid(5, 3);
+        grid.update(b"abcde\r\nfg");
+        grid.resize_cells(10, 3);
+
+        assert_eq!(row_text(&grid, 0), "abcde");
+        assert_eq!(row_text(&grid, 1), "fg");
+    }
+
+    #[test]
+    fn reflow_moves_wide_characters_out_of_the_last_column() {
+        let (mut grid, _) = grid(6, 3);
+        grid.update("ab\u{4e16}\u{754c}".as_bytes());
+        grid.resize_cells(5, 3);
+
+        assert_eq!(row_text(&grid, 0), "ab\u{4e16}");
+        assert_eq!(row_text(&grid, 1), "\u{754c}");
+
+        grid.resize_cells(6, 3);
+
+        assert_eq!(row_text(&grid, 0), "ab\u{4e16}\u{754c}");
+    }
+
+    #[test]
+    fn sgr_indexed_colors() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b[38;5;11;48;5;196m");
+
+        assert_eq!(grid.current_style.foreground, TerminalColor::BrightYellow);
+        assert_eq!(grid.current_style.background, TerminalColor::Rgb(255, 0, 0));
+
+        grid.update(b"\x1b[38:5:3m");
+        assert_eq!(grid.current_style.foreground, TerminalColor::Yellow);
+    }
+
+    #[test]
+    fn sgr_grayscale_ramp() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b[38;5;232;48;5;255m");
+
+        assert_eq!(grid.current_style.foreground, TerminalColor::Rgb(8, 8, 8));
+        assert_eq!(
+            grid.current_style.background,
+            TerminalColor::Rgb(238, 238, 238)
+        );
+    }
+
+    #[test]
+    fn sgr_truecolor_semicolon_form() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b[38;2;10;20;30;1m");
+
+        assert_eq!(
+            grid.current_style.foreground,
+            TerminalColor::Rgb(10, 20, 30)
+        );
+        assert!(grid.current_style.flags.contains(StyleFlags::BOLD));
+    }
+
+    #[test]
+    fn sgr_truecolor_colon_forms() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b[38:2::10:20:30m");
+        assert_eq!(
+            grid.current_style.foreground,
+            TerminalColor::Rgb(10, 20, 30)
+        );
+
+        grid.update(b"\x1b[48:2:40:50:60m");
+        assert_eq!(
+            grid.current_style.background,
+            TerminalColor::Rgb(40, 50, 60)
+        );
+    }
+
+    #[test]
+    fn sgr_underline_subparameter_zero_clears_it() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b[4m");
+        assert!(grid.current_style.flags.contains(StyleFlags::UNDERLINE));
+
+        grid.update(b"\x1b[4:0m");
+        assert!(!grid.current_style.flags.contains(StyleFlags::UNDERLINE));
+    }
+
+    #[test]
+    fn sgr_reset_clears_the_background_and_flags() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b[1;3;7;44m\x1b[0m");
+
+        assert_eq!(grid.current_style, TerminalStyle::default());
+
+        grid.update(b"\x1b[9;41m\x1b[m");
+        assert_eq!(grid.current_style, TerminalStyle::default());
+    }
+
+    #[test]
+    fn osc_sets_the_title_and_icon_name() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b]0;both\x07");
+        assert_eq!(grid.take_title_update(), Some("both"));
+        assert_eq!(grid.icon_name.as_deref(), Some("both"));
+
+        grid.update(b"\x1b]1;icon\x07");
+        assert_eq!(grid.take_title_update(), None);
+        assert_eq!(grid.icon_name.as_deref(), Some("icon"));
+
+        grid.update(b"\x1b]2;title\x1b\\");
+        assert_eq!(grid.take_title_update(), Some("title"));
+        assert_eq!(grid.icon_name.as_deref(), Some("icon"));
+    }
+
+    #[test]
+    fn osc_title_keeps_semicolons() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b]2;vim; a.txt;b\x07");
+
+        assert_eq!(grid.take_title_update(), Some("vim; a.txt;b"));
+    }
+
+    #[test]
+    fn title_stack_pushes_and_pops() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"\x1b]0;shell\x07\x1b[22;0t\x1b]0;vim\x07");
+        assert_eq!(grid.take_title_update(), Some("vim"));
+
+        grid.update(b"\x1b[23;0t");
+        assert_eq!(grid.take_title_update(), Some("shell"));
+        assert_eq!(grid.icon_name.as_deref(), Some("shell"));
+
+        // Popping an empty stack changes nothing
+        grid.update(b"\x1b[23;0t");
+        assert_eq!(grid.take_title_update(), None);
+    }
+
+    #[test]
+    fn title_stack_drops_the_oldest_entry_past_its_limit() {
+        let (mut grid, _) = grid(10, 3);
+        for i in 0..=TITLE_STACK_LIMIT {
+            grid.update(format!("\x1b]2;{i}\x07\x1b[22;2t").as_bytes());
+        }
+
+        for _ in 0..TITLE_STACK_LIMIT {
+            grid.update(b"\x1b[23;2t");
+        }
+        assert_eq!(grid.title.as_deref(), Some("1"));
+
+        grid.update(b"\x1b]2;last\x07\x1b[23;2t");
+        assert_eq!(grid.title.as_deref(), Some("last"));
+    }
+
+    #[test]
+    fn primary_device_attributes() {
+        let (mut grid, responses) = grid(10, 3);
+        grid.update(b"\x1b[c\x1b[0c\x1b[1c");
+
+        assert_eq!(responses.try_recv().unwrap(), "\x1b[?62;22c");
+        assert_eq!(responses.try_recv().unwrap(), "\x1b[?62;22c");
+        assert!(responses.try_recv().is_err());
+    }
+
+    #[test]
+    fn secondary_device_attributes_report_the_version() {
+        let (mut grid, responses) = grid(10, 3);
+        grid.update(b"\x1b[>c");
+
+        let reply = format!("\x1b[>1;{};0c", version_number());
+        assert_eq!(responses.try_recv().unwrap(), reply);
+    }
+
+    #[test]
+    fn xtversion_names_the_terminal() {
+        let (mut grid, responses) = grid(10, 3);
+        grid.update(b"\x1b[>q");
+
+        let reply = format!("\x1bP>|nart({})\x1b\\", env!("CARGO_PKG_VERSION"));
+        assert_eq!(responses.try_recv().unwrap(), reply);
+    }
+
+    #[test]
+    fn backspace_stops_at_the_first_column() {
+        let (mut grid, _) = grid(5, 3);
+        grid.update(b"ab\r\ncd\x08\x08\x08");
+
+        assert_eq!(cursor(&grid), (0, 1));
+    }
+
+    #[test]
+    fn long_runs_of_combining_marks_are_capped() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(format!("a{}b", "\u{301}".repeat(1000)).as_bytes());
+        grid.update("\u{301}".repeat(1000).as_bytes());
+
+        let cluster = grid.cells[0][0].content.to_string();
+        assert_eq!(cluster.chars().count(), MAX_CLUSTER_CHARS);
+        assert!(cluster.starts_with('a'));
+        assert_eq!(
+            grid.cells[0][1].content.to_string().chars().count(),
+            MAX_CLUSTER_CHARS
+        );
+        assert_eq!(cursor(&grid), (2, 0));
+    }
+
+    #[test]
+    fn clusters_continue_across_reads() {
+        let (mut grid, _) = grid(10, 3);
+        grid.update(b"e");
+        grid.update("\u{301}".as_bytes());
+        grid.update("\u{323}x".as_bytes());
+
+        assert_eq!(grid.cells[0][0].content.to_string(), "e\u{301}\u{323}");
+        assert_eq!(cursor(&grid), (2, 0));
+    }
+
+    #[test]
+    fn resizing_on_the_alternate_screen_reflows_the_primary_one() {
+        let (mut grid, _) = grid(10, 5);
+        grid.update(b"abcdefgh\r\nxy\x1b[?1049h");
+        grid.resize_cells(4, 5);
+        grid.update(b"\x1b[?1049l");
+
+        assert_eq!(row_text(&grid, 0), "abcd");
+        assert_eq!(row_text(&grid, 1), "efgh");
+        assert_eq!(row_text(&grid, 2), "xy");
+        assert_eq!(cursor(&grid), (2, 2));
+
+        grid.resize_cells(10, 5);
+
+        assert_eq!(row_text(&grid, 0), "abcdefgh");
+        assert_eq!(row_text(&grid, 1), "xy");
+        assert_eq!(cursor(&grid), (2, 1));
+    }
+
+    #[test]
+    fn resizing_to_an_empty_window_keeps_the_grid() {
+        let (mut grid, _) = grid(10, 5);
+        grid.cell_size = (10.0, 20.0);
+        grid.resize(100, 100);
+        grid.update(b"abcdefgh");
+
+        grid.resize(0, 0);
+        grid.resize(100, 0);
+
+        assert_eq!((grid.columns, grid.rows), (10, 5));
+        assert_eq!(row_text(&grid, 0), "abcdefgh");
     }
 }
@@ -0,0 +1,30 @@
+use winit::keyboard::{Key, NamedKey};
+
+use super::modes::TerminalModes;
+
+/// Returns the sequence sent to the shell for keys that don't produce text,
+/// the cursor keys change with DECCKM
+pub fn encode_key(key: &Key, modes: TerminalModes) -> Option<&'static str> {
+    let Key::Named(key) = key else {
+        return None;
+    };
+
+    let application = modes.contains(TerminalModes::CURSOR_KEYS);
+    let sequence = match (key, application) {
+        (NamedKey::ArrowUp, false) => "\x1b[A",
+        (NamedKey::ArrowDown, false) => "\x1b[B",
+        (NamedKey::ArrowRight, false) => "\x1b[C",
+        (NamedKey::ArrowLeft, false) => "\x1b[D",
+        (NamedKey::Home, false) => "\x1b[H",
+        (NamedKey::End, false) => "\x1b[F",
+        (NamedKey::ArrowUp, true) => "\x1bOA",
+        (NamedKey::ArrowDown, true) => "\x1bOB",
+        (NamedKey::ArrowRight, true) => "\x1bOC",
+        (NamedKey::ArrowLeft, true) => "\x1bOD",
+        (NamedKey::Home, true) => "\x1bOH",
+        (NamedKey::End, true) => "\x1bOF",
+        _ => return None,
+    };
+
+    Some(sequence)
+}
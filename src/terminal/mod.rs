@@ -1,51 +1,141 @@
-use std::os::fd::AsRawFd;
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, MutexGuard, PoisonError,
+    },
+};
 
 use grid::TerminalGrid;
-use nix::{errno::Errno, unistd};
+use nix::unistd::Pid;
 use pty::Pty;
+use pty_io::PtyIo;
+use winit::event_loop::EventLoopProxy;
+use write_queue::WriteQueue;
 
+use crate::config::Config;
+
+pub mod charset;
+pub mod child;
+pub mod grapheme;
 pub mod grid;
+pub mod input;
+pub mod modes;
 pub mod pty;
+pub mod pty_io;
+pub mod reflow;
+pub mod scrollback;
+pub mod write_queue;
+
+/// Sent to the event loop by the threads working on the terminal
+#[derive(Debug, Clone, Copy)]
+pub enum TerminalEvent {
+    /// The grid changed and has to be drawn again
+    ContentChanged,
+    /// The shell exited with the given status and was reaped
+    ChildExited(i32),
+    /// The shell read some of its input, there's room for the input held back
+    InputDrained,
+}
 
 pub struct TerminalState {
     pub pty: Pty,
-    pub grid: TerminalGrid,
+    /// Set once the shell exited, no more input is sent to it
+    pub exit_status: Option<i32>,
+    grid: Arc<Mutex<TerminalGrid>>,
+    write_queue: Arc<WriteQueue>,
+    /// Input that didn't fit in the queue, kept here so the event loop never waits
+    /// for a program that stopped reading
+    pending_input: Vec<u8>,
+    redraw_pending: Arc<AtomicBool>,
 }
 
 impl TerminalState {
-    pub fn new() -> Self {
-        let defualt_shell = std::env::var("SHELL").unwrap();
-        let pty = Pty::new_with_shell(&defualt_shell).unwrap();
-        let grid = TerminalGrid::new(pty.master.as_raw_fd());
+    /// Spawns the shell sized to fit a window of `size` pixels
+    pub fn new(
+        config: &Config,
+        window_id: u64,
+        size: (u32, u32),
+        cell_size: (f32, f32),
+        proxy: EventLoopProxy<TerminalEvent>,
+    ) -> io::Result<Self> {
+        let (sender, responses) = mpsc::channel();
+        let mut grid = TerminalGrid::new(config.scrollback_lines, sender);
+        grid.cell_size = cell_size;
+        grid.resize(size.0, size.1);
+
+        let pty = Pty::new_with_shell(&config.shell, window_id, &grid.winsize(), &config.termios)?;
+
+        let grid = Arc::new(Mutex::new(grid));
+        let redraw_pending = Arc::new(AtomicBool::new(false));
+        let (write_queue, wake) = WriteQueue::new()?;
+        let write_queue = Arc::new(write_queue);
+        let child = pty
+            .child_pid
+            .map(Pid::from_raw)
+            .ok_or_else(|| io::Error::other("The pty has no child process"))?;
+        PtyIo::new(
+            pty.master.try_clone()?,
+            child,
+            grid.clone(),
+            responses,
+            (write_queue.clone(), wake),
+            proxy,
+            redraw_pending.clone(),
+        )
+        .spawn()?;
+
+        Ok(Self {
+            pty,
+            exit_status: None,
+            grid,
+            write_queue,
+            pending_input: Vec::new(),
+            redraw_pending,
+        })
+    }
+
+    /// Locks the grid, the reader thread parses into it while the lock is free
+    pub fn grid(&self) -> MutexGuard<'_, TerminalGrid> {
+        self.grid.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Marks the pending [`TerminalEvent::ContentChanged`] as handled, the next
+    /// output wakes the event loop again
+    pub fn redraw_handled(&self) {
+        self.redraw_pending.store(false, Ordering::Release);
+    }
+
+    /// Records the exit of the shell and prints its status below the output, the
+    /// hint is printed after it when there's something more to do
+    pub fn child_exited(&mut self, status: i32, hint: Option<&str>) {
+        self.exit_status = Some(status);
+        self.pty.child_pid = None;
 
-        Self { pty, grid }
+        let hint = hint.map(|hint| format!(", {hint}")).unwrap_or_default();
+        let message = format!("\r\n[process exited with status {status}{hint}]");
+        self.grid().update(message.as_bytes());
     }
 
+    /// Queues input for the shell, it's written by the I/O thread in the same order
+    /// it was queued
     pub fn write_content(&mut self, buf: &str) {
-        match unistd::write(&self.pty.master, buf.as_bytes()) {
-            Ok(_) => (),
-            Err(e) => log::error!("Error writting to the master: {e}"),
-        }
+        self.pending_input.extend_from_slice(buf.as_bytes());
+        self.flush_input();
     }
 
-    pub fn resize_grid(&mut self, new_size: (u32, u32), cell_size: (f32, f32)) {
-        self.grid.cell_size = cell_size;
-        self.grid.resize(new_size.0, new_size.1);
+    /// Queues the input held back because the queue was full, as much as fits
+    pub fn flush_input(&mut self) {
+        let queued = self.write_queue.push(&self.pending_input);
+        self.pending_input.drain(..queued);
     }
 
-    pub fn read_content(&mut self) -> Vec<u8> {
-        let mut read_buffer = [0; 65536];
-        let content = match unistd::read(&self.pty.master, &mut read_buffer) {
-            Ok(bytes_read) => read_buffer[..bytes_read].to_vec(),
-            Err(e) => {
-                match e {
-                    Errno::EAGAIN | Errno::EIO => (),
-                    e => log::error!("Error while reading the master: {e}"),
-                }
-                Vec::new()
-            }
-        };
-
-        content
+    /// Resizes the grid first and then the pty, so the program never gets a size
+    /// the grid doesn't have yet
+    pub fn resize_grid(&mut self, new_size: (u32, u32), cell_size: (f32, f32)) -> nix::Result<()> {
+        let mut grid = self.grid();
+        grid.cell_size = cell_size;
+        grid.resize(new_size.0, new_size.1);
+        self.pty.resize(&grid.winsize())
     }
 }
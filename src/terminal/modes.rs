@@ -0,0 +1,40 @@
+bitflags::bitflags! {
+    /// DEC private modes, set with `CSI ? Pn h` and reset with `CSI ? Pn l`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TerminalModes: u16 {
+        /// DECCKM, cursor keys send application sequences (`ESC O A`)
+        const CURSOR_KEYS = 1;
+        /// DECSCNM, the default foreground and background colors are swapped
+        const REVERSE_VIDEO = 1 << 1;
+        /// DECOM, cursor addressing is relative to the scroll region
+        const ORIGIN = 1 << 2;
+        /// DECAWM, printing past the right margin wraps to the next line
+        const AUTO_WRAP = 1 << 3;
+        /// DECTCEM, the cursor is visible
+        const SHOW_CURSOR = 1 << 4;
+        /// DECLRMM, left and right margins can be set with DECSLRM
+        const LEFT_RIGHT_MARGIN = 1 << 5;
+    }
+}
+
+impl TerminalModes {
+    /// Maps the number of a private mode to its flag, `None` for modes that are
+    /// not part of the registry
+    pub fn from_private(mode: u16) -> Option<Self> {
+        match mode {
+            1 => Some(Self::CURSOR_KEYS),
+            5 => Some(Self::REVERSE_VIDEO),
+            6 => Some(Self::ORIGIN),
+            7 => Some(Self::AUTO_WRAP),
+            25 => Some(Self::SHOW_CURSOR),
+            69 => Some(Self::LEFT_RIGHT_MARGIN),
+            _ => None,
+        }
+    }
+}
+
+impl Default for TerminalModes {
+    fn default() -> Self {
+        Self::AUTO_WRAP | Self::SHOW_CURSOR
+    }
+}
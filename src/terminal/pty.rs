@@ -1,13 +1,33 @@
+use crate::config::{ShellConfig, TermiosConfig};
 use nix::{
-    fcntl::{FcntlArg, OFlag},
-    pty::ForkptyResult,
-    unistd,
+    fcntl::{FcntlArg, FdFlag, OFlag},
+    libc,
+    pty::{ForkptyResult, Winsize},
+    sys::{
+        signal::{self, SigHandler, Signal},
+        termios::{self, InputFlags, SpecialCharacterIndices, Termios},
+    },
+    unistd::{self, User},
 };
 use std::{
+    collections::BTreeMap,
+    ffi::{CString, OsStr, OsString},
     io,
-    os::fd::{AsFd, FromRawFd, IntoRawFd},
+    os::{
+        fd::{AsFd, AsRawFd, FromRawFd, IntoRawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::Path,
 };
 
+/// Used when neither `$SHELL` nor the passwd entry name a shell
+const FALLBACK_SHELL: &str = "/bin/sh";
+
+/// The terminfo entry describing what nart supports
+const TERM: &str = "xterm-256color";
+
+nix::ioctl_write_ptr_bad!(tiocswinsz, nix::libc::TIOCSWINSZ, Winsize);
+
 #[derive(Debug)]
 pub struct Pty {
     pub master: std::fs::File,
@@ -15,8 +35,20 @@ pub struct Pty {
 }
 
 impl Pty {
-    pub fn new_with_shell(command: &str) -> Result<Self, io::Error> {
-        let forked_pty = unsafe { nix::pty::forkpty(None, None)? };
+    /// Spawns the shell in a new pty, the program starts with the given window size
+    /// instead of learning it on the first resize. `window_id` identifies the
+    /// window in the environment of the shell
+    pub fn new_with_shell(
+        shell: &ShellConfig,
+        window_id: u64,
+        winsize: &Winsize,
+        termios_config: &TermiosConfig,
+    ) -> Result<Self, io::Error> {
+        let termios = default_termios(termios_config)?;
+        // Everything the child needs is allocated before forking, the child of a
+        // process with other threads may only make async-signal-safe calls
+        let command = ShellCommand::new(shell, window_id)?;
+        let forked_pty = unsafe { nix::pty::forkpty(Some(winsize), Some(&termios))? };
 
         let pty = match forked_pty {
             ForkptyResult::Parent { child, master } => {
@@ -27,24 +59,194 @@ impl Pty {
                     master.as_fd(),
                     FcntlArg::F_SETFL(OFlag::from_bits_retain(flags) | OFlag::O_NONBLOCK),
                 )?;
+                nix::fcntl::fcntl(master.as_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
 
                 Self {
                     master,
                     child_pid: Some(child.as_raw()),
                 }
             }
-            ForkptyResult::Child => {
-                let exit_code = std::process::Command::new(command).spawn()?.wait()?.code();
-                std::process::exit(exit_code.unwrap_or(0));
-            }
+            ForkptyResult::Child => command.exec(),
         };
 
         Ok(pty)
     }
 
+    /// Tells the program running in the pty about a new window size
+    pub fn resize(&self, winsize: &Winsize) -> nix::Result<()> {
+        unsafe { tiocswinsz(self.master.as_raw_fd(), winsize) }.map(drop)
+    }
+
+    /// Hangs up the session, the shell is the leader of its process group and the
+    /// foreground job (if any) gets the signal too
     pub fn close(&mut self) {
-        if let Some(pid) = self.child_pid {
-            let _ = nix::sys::signal::kill(unistd::Pid::from_raw(pid), nix::sys::signal::SIGTERM);
+        let Some(pid) = self.child_pid.take() else {
+            return;
+        };
+
+        let session = unistd::Pid::from_raw(pid);
+        let _ = signal::killpg(session, Signal::SIGHUP);
+
+        match unistd::tcgetpgrp(&self.master) {
+            Ok(foreground) if foreground != session => {
+                let _ = signal::killpg(foreground, Signal::SIGHUP);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// The settings of a fresh pty with the configured changes applied, nix can only
+/// build a [`Termios`] by reading it from a terminal
+fn default_termios(config: &TermiosConfig) -> nix::Result<Termios> {
+    let pty = nix::pty::openpty(None, None)?;
+    let mut termios = termios::tcgetattr(&pty.slave)?;
+
+    termios.input_flags.set(InputFlags::IUTF8, config.utf8);
+    termios.control_chars[SpecialCharacterIndices::VERASE as usize] = config.erase;
+
+    Ok(termios)
+}
+
+/// The arguments of `execvpe`, prepared before forking
+struct ShellCommand {
+    program: CString,
+    /// Null terminated arrays pointing into `_strings`, which owns the arguments
+    /// followed by the environment
+    argv: Vec<*const libc::c_char>,
+    envp: Vec<*const libc::c_char>,
+    _strings: Vec<CString>,
+    /// The directory to start in and the message printed when it can't be entered
+    working_directory: Option<(CString, CString)>,
+    /// Printed when exec fails, the child can't format it after forking
+    failure: CString,
+}
+
+impl ShellCommand {
+    fn new(shell: &ShellConfig, window_id: u64) -> io::Result<Self> {
+        let program = shell.program.clone().unwrap_or_else(default_shell);
+
+        // A login shell is told by a `-` in front of its name
+        let argv0 = if shell.login {
+            let name = Path::new(&program)
+                .file_name()
+                .unwrap_or(OsStr::new(&program));
+            [b"-", name.as_bytes()].concat()
+        } else {
+            program.clone().into_bytes()
+        };
+
+        let args = std::iter::once(argv0)
+            .chain(shell.args.iter().map(|arg| arg.clone().into_bytes()))
+            .map(CString::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        let env = shell_env(shell, window_id)
+            .into_iter()
+            .map(|(name, value)| CString::new([name.as_bytes(), b"=", value.as_bytes()].concat()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let working_directory = shell
+            .working_directory
+            .as_ref()
+            .map(|dir| -> io::Result<_> {
+                let failure = format!("nart: failed to change to {}\n", dir.display());
+                Ok((
+                    CString::new(dir.as_os_str().as_bytes())?,
+                    CString::new(failure)?,
+                ))
+            })
+            .transpose()?;
+
+        // The pointers stay valid when the strings are moved, their bytes don't move
+        let argv = null_terminated(&args);
+        let envp = null_terminated(&env);
+
+        Ok(Self {
+            failure: CString::new(format!("nart: failed to start {program}\n"))?,
+            program: CString::new(program)?,
+            argv,
+            envp,
+            _strings: args.into_iter().chain(env).collect(),
+            working_directory,
+        })
+    }
+
+    /// Replaces the forked child with the shell, the process exits with 127 like
+    /// shells do when the program can't be run
+    fn exec(self) -> ! {
+        // A missing directory isn't fatal, the shell starts where nart was started
+        if let Some((dir, failure)) = &self.working_directory {
+            if unistd::chdir(dir.as_c_str()).is_err() {
+                let _ = unistd::write(io::stderr(), failure.as_bytes());
+            }
         }
+
+        // Rust ignores SIGPIPE and ignored signals survive exec
+        let _ = unsafe { signal::signal(Signal::SIGPIPE, SigHandler::SigDfl) };
+
+        unsafe {
+            libc::execvpe(
+                self.program.as_ptr(),
+                self.argv.as_ptr(),
+                self.envp.as_ptr(),
+            )
+        };
+        let _ = unistd::write(io::stderr(), self.failure.as_bytes());
+        unsafe { libc::_exit(127) }
+    }
+}
+
+/// The pointers to `strings` followed by a null pointer, as exec takes them
+fn null_terminated(strings: &[CString]) -> Vec<*const libc::c_char> {
+    strings
+        .iter()
+        .map(|string| string.as_ptr())
+        .chain(std::iter::once(std::ptr::null()))
+        .collect()
+}
+
+/// The environment of nart with the variables describing the terminal, then the
+/// configured changes
+fn shell_env(shell: &ShellConfig, window_id: u64) -> BTreeMap<OsString, OsString> {
+    let mut env: BTreeMap<_, _> = std::env::vars_os().collect();
+
+    let terminal = [
+        ("TERM", TERM.to_string()),
+        ("COLORTERM", "truecolor".to_string()),
+        ("TERM_PROGRAM", "nart".to_string()),
+        (
+            "TERM_PROGRAM_VERSION",
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        ("NART_WINDOW_ID", window_id.to_string()),
+    ];
+    let configured = shell
+        .env
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.clone()));
+    for (name, value) in terminal.into_iter().chain(configured) {
+        env.insert(name.into(), value.into());
+    }
+
+    for name in &shell.unset_env {
+        env.remove(OsStr::new(name));
     }
+
+    env
+}
+
+/// `$SHELL`, then the shell of the user in the passwd database
+fn default_shell() -> String {
+    if let Some(shell) = std::env::var("SHELL")
+        .ok()
+        .filter(|shell| !shell.is_empty())
+    {
+        return shell;
+    }
+
+    User::from_uid(unistd::getuid())
+        .ok()
+        .flatten()
+        .and_then(|user| user.shell.into_os_string().into_string().ok())
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| FALLBACK_SHELL.to_string())
 }
@@ -0,0 +1,185 @@
+use std::{
+    fs::File,
+    io,
+    os::fd::{AsFd, OwnedFd},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Receiver,
+        Arc, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+};
+
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    unistd::{self, Pid},
+};
+use winit::event_loop::EventLoopProxy;
+
+use super::{child, grid::TerminalGrid, write_queue::WriteQueue, TerminalEvent};
+
+const READ_BUFFER_SIZE: usize = 65536;
+
+/// Reads the output of the shell and writes the queued input on its own thread,
+/// the thread sleeps in `poll` until the master has something to read or can take
+/// more input so an idle terminal costs nothing
+pub struct PtyIo {
+    master: File,
+    child: Pid,
+    grid: Arc<Mutex<TerminalGrid>>,
+    responses: Receiver<String>,
+    queue: Arc<WriteQueue>,
+    /// Read end of the pipe [`WriteQueue`] writes to when there's new input
+    wake: OwnedFd,
+    proxy: EventLoopProxy<TerminalEvent>,
+    /// Set while a [`TerminalEvent::ContentChanged`] wasn't handled yet, so a burst
+    /// of output wakes the event loop only once
+    redraw_pending: Arc<AtomicBool>,
+}
+
+impl PtyIo {
+    pub fn new(
+        master: File,
+        child: Pid,
+        grid: Arc<Mutex<TerminalGrid>>,
+        responses: Receiver<String>,
+        (queue, wake): (Arc<WriteQueue>, OwnedFd),
+        proxy: EventLoopProxy<TerminalEvent>,
+        redraw_pending: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            master,
+            child,
+            grid,
+            responses,
+            queue,
+            wake,
+            proxy,
+            redraw_pending,
+        }
+    }
+
+    pub fn spawn(self) -> io::Result<JoinHandle<()>> {
+        thread::Builder::new()
+            .name("pty-io".to_string())
+            .spawn(move || self.run())
+    }
+
+    fn run(self) {
+        let status = self.poll_master();
+        self.queue.close();
+
+        // The master hangs up as the shell exits, the status may not be there yet
+        if let Some(status) = status.or_else(|| child::reap(self.child, true)) {
+            let _ = self.proxy.send_event(TerminalEvent::ChildExited(status));
+        }
+    }
+
+    /// Runs until the master hangs up or the shell exits, returns the exit status
+    /// when the shell was reaped
+    fn poll_master(&self) -> Option<i32> {
+        let sigchld = match child::sigchld_fd() {
+            Ok(fd) => fd,
+            Err(e) => {
+                log::error!("Failed to watch the shell: {e}");
+                return None;
+            }
+        };
+        let mut buffer = vec![0; READ_BUFFER_SIZE];
+
+        loop {
+            // Only ask for POLLOUT with input waiting, the master is almost always writable
+            let mut master_events = PollFlags::POLLIN;
+            if !self.queue.is_empty() {
+                master_events |= PollFlags::POLLOUT;
+            }
+
+            let mut fds = [
+                PollFd::new(self.master.as_fd(), master_events),
+                PollFd::new(self.wake.as_fd(), PollFlags::POLLIN),
+                PollFd::new(sigchld, PollFlags::POLLIN),
+            ];
+            match poll(&mut fds, PollTimeout::NONE) {
+                Ok(_) | Err(Errno::EINTR) => (),
+                Err(e) => {
+                    log::error!("Error while polling the master: {e}");
+                    return None;
+                }
+            }
+
+            let master_events = fds[0].revents().unwrap_or(PollFlags::empty());
+            let readable = |fd: &PollFd| {
+                fd.revents()
+                    .is_some_and(|events| events.contains(PollFlags::POLLIN))
+            };
+            let (woken, child_changed) = (readable(&fds[1]), readable(&fds[2]));
+
+            if child_changed {
+                child::drain_sigchld(sigchld);
+                if let Some(status) = child::reap(self.child, false) {
+                    self.drain_master(&mut buffer);
+                    return Some(status);
+                }
+            }
+
+            if woken {
+                self.drain_wake();
+            }
+
+            if let Err(e) = self.queue.flush(&self.master) {
+                log::error!("Error writting to the master: {e}");
+            }
+
+            if self.queue.take_freed_space() {
+                let _ = self.proxy.send_event(TerminalEvent::InputDrained);
+            }
+
+            if !master_events
+                .intersects(PollFlags::POLLIN | PollFlags::POLLHUP | PollFlags::POLLERR)
+            {
+                continue;
+            }
+
+            match unistd::read(&self.master, &mut buffer) {
+                // The slave side is closed once the shell exits, linux reports it as EIO
+                Ok(0) | Err(Errno::EIO) => return None,
+                Ok(bytes_read) => self.parse(&buffer[..bytes_read]),
+                Err(Errno::EAGAIN | Errno::EINTR) => (),
+                Err(e) => {
+                    log::error!("Error while reading the master: {e}");
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Parses what the shell printed right before exiting
+    fn drain_master(&self, buffer: &mut [u8]) {
+        while let Ok(bytes_read @ 1..) = unistd::read(&self.master, buffer) {
+            self.parse(&buffer[..bytes_read]);
+        }
+    }
+
+    fn drain_wake(&self) {
+        let mut buffer = [0; 64];
+        while let Ok(1..) = unistd::read(&self.wake, &mut buffer) {}
+    }
+
+    /// Feeds the output to the grid, writes back the replies to any query found
+    /// on it and wakes the event loop up to draw it
+    fn parse(&self, data: &[u8]) {
+        self.grid
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .update(data);
+
+        for response in self.responses.try_iter() {
+            self.queue.push_reply(response.as_bytes());
+        }
+
+        if !self.redraw_pending.swap(true, Ordering::AcqRel) {
+            let _ = self.proxy.send_event(TerminalEvent::ContentChanged);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use super::grid::{CellFlags, TerminalCell};
+
+/// Joins the soft wrapped rows back into the lines the program printed and wraps
+/// them again at `columns`. The cursor is given and returned as `(column, row)`
+/// and follows the cell it was on
+pub fn reflow(
+    rows: Vec<Vec<TerminalCell>>,
+    columns: usize,
+    cursor: (usize, usize),
+) -> (Vec<Vec<TerminalCell>>, (usize, usize)) {
+    let mut reflowed = Reflowed {
+        rows: Vec::with_capacity(rows.len()),
+        columns,
+        cursor: (0, 0),
+    };
+    let mut line = Vec::new();
+    let mut cursor_offset = None;
+
+    for (index, mut row) in rows.into_iter().enumerate() {
+        let wrapped = row
+            .last()
+            .is_some_and(|cell| cell.flags.contains(CellFlags::WRAPLINE));
+        if wrapped {
+            row.retain(|cell| !cell.flags.contains(CellFlags::LEADING_WIDE_SPACER));
+            if let Some(cell) = row.last_mut() {
+                cell.flags.remove(CellFlags::WRAPLINE);
+            }
+        }
+
+        if index == cursor.1 {
+            cursor_offset = Some(line.len() + cursor.0);
+        }
+        line.extend(row);
+
+        if !wrapped {
+            reflowed.push_line(std::mem::take(&mut line), cursor_offset.take());
+        }
+    }
+
+    if !line.is_empty() || cursor_offset.is_some() {
+        reflowed.push_line(line, cursor_offset);
+    }
+
+    (reflowed.rows, reflowed.cursor)
+}
+
+struct Reflowed {
+    rows: Vec<Vec<TerminalCell>>,
+    columns: usize,
+    cursor: (usize, usize),
+}
+
+impl Reflowed {
+    /// Wraps a logical line into rows, `cursor_offset` is the index of the cell
+    /// the cursor is on when the cursor is in this line
+    fn push_line(&mut self, mut line: Vec<TerminalCell>, cursor_offset: Option<usize>) {
+        let len = line
+            .iter()
+            .rposition(|cell| !cell.is_blank())
+            .map_or(0, |i| i + 1);
+        line.truncate(len);
+
+        let mut row = Vec::with_capacity(self.columns);
+        for (index, cell) in line.into_iter().enumerate() {
+            // A wide character never starts in the last column, it's moved to the
+            // next row leaving a padding cell behind
+            let padding = cell.flags.contains(CellFlags::WIDE)
+                && self.columns >= 2
+                && row.len() + 2 > self.columns;
+            if padding {
+                row.resize(
+                    self.columns,
+                    TerminalCell {
+                        flags: CellFlags::LEADING_WIDE_SPACER,
+                        ..Default::default()
+                    },
+                );
+            }
+
+            if row.len() >= self.columns {
+                self.push_wrapped_row(std::mem::take(&mut row));
+            }
+
+            if cursor_offset == Some(index) {
+                self.cursor = (row.len(), self.rows.len());
+            }
+            row.push(cell);
+        }
+
+        // The cursor can be past the text of its line, the blanks it moved over
+        // were not kept
+        if let Some(offset) = cursor_offset.filter(|offset| *offset >= len) {
+            let column = row.len() + offset - len;
+            self.cursor = (
+                column % self.columns,
+                self.rows.len() + column / self.columns,
+            );
+        }
+
+        self.rows.push(row);
+        while self.rows.len() <= self.cursor.1 {
+            self.rows.push(Vec::new());
+        }
+    }
+
+    fn push_wrapped_row(&mut self, mut row: Vec<TerminalCell>) {
+        if let Some(cell) = row.last_mut() {
+            cell.flags.insert(CellFlags::WRAPLINE);
+        }
+
+        self.rows.push(row);
+    }
+}
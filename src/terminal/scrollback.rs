@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+
+use super::grid::TerminalCell;
+
+/// Lines that scrolled off the top of the screen, kept in a ring buffer so the
+/// oldest line is dropped once `limit` is reached
+#[derive(Debug)]
+pub struct Scrollback {
+    lines: VecDeque<Box<[TerminalCell]>>,
+    limit: usize,
+}
+
+impl Scrollback {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            limit,
+        }
+    }
+
+    /// Stores a line at the bottom of the history, the trailing blank cells are
+    /// not stored to keep every line as small as possible
+    pub fn push(&mut self, mut line: Vec<TerminalCell>) {
+        if self.limit == 0 {
+            return;
+        }
+
+        let len = line
+            .iter()
+            .rposition(|c| !c.is_blank())
+            .map_or(0, |i| i + 1);
+        line.truncate(len);
+
+        if self.lines.len() >= self.limit {
+            self.lines.pop_front();
+        }
+
+        self.lines.push_back(line.into_boxed_slice());
+    }
+
+    /// Removes every line from the history, from the oldest to the newest
+    pub fn take_lines(&mut self) -> Vec<Vec<TerminalCell>> {
+        self.lines.drain(..).map(Vec::from).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Iterates over the last `count` lines, from the oldest to the newest
+    pub fn last_lines(&self, count: usize) -> impl Iterator<Item = &[TerminalCell]> {
+        let start = self.lines.len().saturating_sub(count);
+        self.lines.range(start..).map(|line| &line[..])
+    }
+}
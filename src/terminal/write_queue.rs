@@ -0,0 +1,228 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    os::fd::OwnedFd,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use nix::{errno::Errno, fcntl::OFlag, unistd};
+
+/// Bytes the queue holds before [`WriteQueue::push`] refuses more until the
+/// shell reads some of them
+const CAPACITY: usize = 4 * 1024 * 1024;
+
+/// Input waiting to be written to the master. The event loop queues it and the
+/// I/O thread writes it whenever the master is writable, so a large paste never
+/// gets truncated and the bytes always arrive in the order they were queued
+#[derive(Debug)]
+pub struct WriteQueue {
+    state: Mutex<QueueState>,
+    capacity: usize,
+    /// Write end of a pipe polled by the I/O thread, wakes it up to write new input
+    wake: OwnedFd,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    bytes: VecDeque<u8>,
+    /// The I/O thread is gone, nothing will drain the queue anymore
+    closed: bool,
+    /// A push didn't fit, whoever pushed it waits to hear there's room again
+    refused: bool,
+}
+
+impl WriteQueue {
+    /// Creates the queue and the read end of its wake up pipe
+    pub fn new() -> nix::Result<(Self, OwnedFd)> {
+        Self::with_capacity(CAPACITY)
+    }
+
+    fn with_capacity(capacity: usize) -> nix::Result<(Self, OwnedFd)> {
+        let (wake_receiver, wake) = unistd::pipe2(OFlag::O_NONBLOCK | OFlag::O_CLOEXEC)?;
+        let queue = Self {
+            state: Mutex::default(),
+            capacity,
+            wake,
+        };
+
+        Ok((queue, wake_receiver))
+    }
+
+    /// Queues as much of `data` as fits after everything queued before it and
+    /// returns how much that was. It never waits, the event loop keeps the rest
+    /// until [`Self::take_freed_space`] says there's room
+    pub fn push(&self, data: &[u8]) -> usize {
+        let mut state = self.lock();
+        // Input for a shell that's gone is dropped
+        if state.closed {
+            return data.len();
+        }
+
+        let len = self
+            .capacity
+            .saturating_sub(state.bytes.len())
+            .min(data.len());
+        state.bytes.extend(&data[..len]);
+        state.refused |= len < data.len();
+        drop(state);
+
+        if len > 0 {
+            self.wake();
+        }
+        len
+    }
+
+    /// Queues `data` even past the capacity, used by the I/O thread itself for the
+    /// replies to queries since it's the one draining the queue
+    pub fn push_reply(&self, data: &[u8]) {
+        let mut state = self.lock();
+        if !state.closed {
+            state.bytes.extend(data);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().bytes.is_empty()
+    }
+
+    /// Writes as much of the queue as the master takes without blocking, partial
+    /// writes leave the rest at the front of the queue
+    pub fn flush(&self, master: &File) -> nix::Result<()> {
+        let mut state = self.lock();
+
+        loop {
+            let (front, _) = state.bytes.as_slices();
+            if front.is_empty() {
+                return Ok(());
+            }
+
+            match unistd::write(master, front) {
+                Ok(written) => {
+                    state.bytes.drain(..written);
+                }
+                Err(Errno::EINTR) => (),
+                Err(Errno::EAGAIN) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Whether a push was refused since the last call and there's room for it now
+    pub fn take_freed_space(&self) -> bool {
+        let mut state = self.lock();
+        let freed = state.refused && state.bytes.len() < self.capacity;
+        if freed {
+            state.refused = false;
+        }
+
+        freed
+    }
+
+    /// Drops the queued input, later pushes are dropped too
+    pub fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        state.bytes.clear();
+    }
+
+    fn wake(&self) {
+        // A full pipe already has a wake up pending
+        match unistd::write(&self.wake, &[0]) {
+            Ok(_) | Err(Errno::EAGAIN) => (),
+            Err(e) => log::error!("Failed to wake up the pty thread: {e}"),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::AsFd;
+
+    use super::*;
+
+    /// A queue and a non blocking pipe standing in for the master
+    fn queue(capacity: usize) -> (WriteQueue, File, OwnedFd) {
+        let (queue, _) = WriteQueue::with_capacity(capacity).unwrap();
+        let (reader, writer) = unistd::pipe2(OFlag::O_NONBLOCK).unwrap();
+
+        (queue, File::from(writer), reader)
+    }
+
+    fn read_all(reader: impl AsFd) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut buffer = [0; 4096];
+        while let Ok(read @ 1..) = unistd::read(&reader, &mut buffer) {
+            data.extend(&buffer[..read]);
+        }
+
+        data
+    }
+
+    #[test]
+    fn push_takes_only_what_fits() {
+        let (queue, master, reader) = queue(8);
+        assert_eq!(queue.push(b"abcde"), 5);
+        assert_eq!(queue.push(b"fghij"), 3);
+        assert_eq!(queue.push(b"k"), 0);
+
+        queue.flush(&master).unwrap();
+        assert_eq!(read_all(reader), b"abcdefgh");
+    }
+
+    #[test]
+    fn input_is_written_in_order() {
+        let (queue, master, reader) = queue(64);
+        queue.push(b"one ");
+        queue.push_reply(b"two ");
+        queue.push(b"three");
+
+        queue.flush(&master).unwrap();
+        assert_eq!(read_all(reader), b"one two three");
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn partial_writes_keep_the_rest_queued() {
+        let data = (0..1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+        let (queue, master, reader) = queue(data.len());
+        assert_eq!(queue.push(&data), data.len());
+
+        // The pipe takes much less than a megabyte before it's full
+        let mut written = Vec::new();
+        while !queue.is_empty() {
+            queue.flush(&master).unwrap();
+            written.extend(read_all(&reader));
+        }
+
+        assert_eq!(written, data);
+    }
+
+    #[test]
+    fn freed_space_is_reported_once_after_a_refused_push() {
+        let (queue, master, _reader) = queue(4);
+        queue.push(b"ab");
+        assert!(!queue.take_freed_space());
+
+        assert_eq!(queue.push(b"cdef"), 2);
+        assert!(!queue.take_freed_space());
+
+        queue.flush(&master).unwrap();
+        assert!(queue.take_freed_space());
+        assert!(!queue.take_freed_space());
+    }
+
+    #[test]
+    fn closed_queue_drops_input() {
+        let (queue, master, reader) = queue(4);
+        queue.push(b"ab");
+        queue.close();
+
+        assert_eq!(queue.push(b"abcdef"), 6);
+        queue.flush(&master).unwrap();
+        assert!(read_all(reader).is_empty());
+    }
+}
@@ -1,86 +1,223 @@
 use log::error;
 use winit::{
     application::ApplicationHandler,
-    event::{ElementState, KeyEvent, WindowEvent},
+    dpi::PhysicalSize,
+    event::{ElementState, KeyEvent, MouseScrollDelta, WindowEvent},
+    event_loop::{ActiveEventLoop, EventLoop, EventLoopProxy},
+    keyboard::{Key, ModifiersState, NamedKey},
 };
 
-use crate::{graphics::renderer::Renderer, terminal};
+use crate::{
+    config::{Config, ExitPolicy},
+    graphics::renderer::Renderer,
+    terminal::{self, grid::DEFAULT_TITLE, input, TerminalEvent},
+};
+
+/// Lines moved per mouse wheel notch when scrolling through the history
+const WHEEL_SCROLL_LINES: f32 = 3.0;
 
 pub struct Nart {
     renderer: Option<Renderer>,
-    terminal: terminal::TerminalState,
-    content: Vec<u8>,
+    /// Spawned once the window exists, its size is needed to size the pty
+    terminal: Option<terminal::TerminalState>,
+    config: Config,
+    proxy: EventLoopProxy<TerminalEvent>,
+    modifiers: ModifiersState,
+    /// The last size from a burst of resize events, applied once per frame
+    pending_resize: Option<PhysicalSize<u32>>,
 }
 
 impl Nart {
-    pub fn new() -> Self {
-        let state = terminal::TerminalState::new();
-
+    pub fn new(proxy: EventLoopProxy<TerminalEvent>) -> Self {
         Self {
             renderer: None,
-            terminal: state,
-            content: Vec::new(),
+            terminal: None,
+            config: Config::load(),
+            proxy,
+            modifiers: ModifiersState::empty(),
+            pending_resize: None,
         }
     }
 }
 
-impl ApplicationHandler for Nart {
-    fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        let window_attrs = winit::window::Window::default_attributes().with_title("Nart");
+fn render_grid(renderer: &mut Renderer, grid: &terminal::grid::TerminalGrid) {
+    let cursor_pos = grid.get_cursor();
+
+    renderer.update_modes(grid.modes());
+    renderer.write_content(grid.get_content().as_slice());
+    renderer.update_cursor(cursor_pos.0, cursor_pos.1, grid.cell_size);
+}
+
+/// Starts a shell sized to fit the window
+fn spawn_terminal(
+    config: &Config,
+    renderer: &mut Renderer,
+    proxy: &EventLoopProxy<TerminalEvent>,
+) -> std::io::Result<terminal::TerminalState> {
+    let window = renderer.window();
+    let size = window.inner_size();
+    let cell_size = renderer.get_cell_size();
+
+    terminal::TerminalState::new(
+        config,
+        window.id().into(),
+        (size.width, size.height),
+        cell_size,
+        proxy.clone(),
+    )
+}
+
+impl ApplicationHandler<TerminalEvent> for Nart {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        let window_attrs = winit::window::Window::default_attributes().with_title(DEFAULT_TITLE);
 
         let window = event_loop.create_window(window_attrs).unwrap();
-        let renderer = Renderer::new(window);
+        let mut renderer = Renderer::new(window);
+
+        match spawn_terminal(&self.config, &mut renderer, &self.proxy) {
+            Ok(state) => self.terminal = Some(state),
+            Err(e) => {
+                error!("Failed to spawn the shell: {e}");
+                event_loop.exit();
+            }
+        }
+
         self.renderer = Some(renderer);
     }
 
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: TerminalEvent) {
+        let (Some(renderer), Some(terminal)) = (self.renderer.as_ref(), self.terminal.as_mut())
+        else {
+            return;
+        };
+
+        match event {
+            TerminalEvent::ContentChanged => renderer.window().request_redraw(),
+            TerminalEvent::InputDrained => terminal.flush_input(),
+            TerminalEvent::ChildExited(status) => {
+                match self.config.exit_policy {
+                    ExitPolicy::Close => return event_loop.exit(),
+                    ExitPolicy::Hold => terminal.child_exited(status, None),
+                    ExitPolicy::Respawn => {
+                        terminal.child_exited(status, Some("press enter to restart"))
+                    }
+                }
+                renderer.window().request_redraw();
+            }
+        }
+    }
+
     fn window_event(
         &mut self,
-        event_loop: &winit::event_loop::ActiveEventLoop,
+        event_loop: &ActiveEventLoop,
         _window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
-        let renderer = self.renderer.as_mut().unwrap();
-        let _window = renderer.window();
+        let (Some(renderer), Some(terminal)) = (self.renderer.as_mut(), self.terminal.as_mut())
+        else {
+            return;
+        };
+        let window = renderer.window();
 
         match event {
             WindowEvent::CloseRequested => {
-                self.terminal.pty.close();
+                terminal.pty.close();
                 event_loop.exit();
             }
             WindowEvent::RedrawRequested => {
-                let mut content = self.terminal.read_content();
-
-                if !content.is_empty() {
-                    self.terminal.grid.update(content.as_slice());
-                    let cursor_pos = self.terminal.grid.get_cursor();
+                if let Some(size) = self.pending_resize.take() {
+                    let cell_size = renderer.get_cell_size();
+                    if let Err(e) = terminal.resize_grid((size.width, size.height), cell_size) {
+                        error!("Failed to resize the pty: {e}");
+                    }
+                }
 
-                    renderer.write_content(self.terminal.grid.get_content());
-                    renderer.update_cursor(
-                        cursor_pos.0,
-                        cursor_pos.1,
-                        self.terminal.grid.cell_size,
-                    );
+                terminal.redraw_handled();
+                let mut grid = terminal.grid();
+                render_grid(renderer, &grid);
 
-                    self.content.append(&mut content);
+                if let Some(title) = grid.take_title_update() {
+                    window.set_title(title);
                 }
+                drop(grid);
 
                 renderer.init_draw();
             }
             WindowEvent::Resized(size) => {
-                self.terminal
-                    .resize_grid((size.width, size.height), renderer.get_cell_size());
-                renderer.resize(size)
+                // A minimized window has no size, the terminal keeps its own
+                if size.width == 0 || size.height == 0 {
+                    return;
+                }
+
+                self.pending_resize = Some(size);
+                renderer.resize(size);
+                window.request_redraw();
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let mut grid = terminal.grid();
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y * WHEEL_SCROLL_LINES,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / grid.cell_size.1.max(1.0),
+                };
+
+                grid.scroll_display(lines.round() as i32);
+                window.request_redraw();
             }
             WindowEvent::KeyboardInput {
                 event:
                     KeyEvent {
-                        text: Some(text),
+                        logical_key: Key::Named(key @ (NamedKey::PageUp | NamedKey::PageDown)),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if self.modifiers.shift_key() => {
+                let mut grid = terminal.grid();
+                let page = grid.rows as i32;
+                let delta = if key == NamedKey::PageUp { page } else { -page };
+
+                grid.scroll_display(delta);
+                window.request_redraw();
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        logical_key,
+                        text,
                         state: ElementState::Pressed,
                         ..
                     },
                 ..
             } => {
-                self.terminal.write_content(text.as_str());
+                if terminal.exit_status.is_some() {
+                    let respawn = self.config.exit_policy == ExitPolicy::Respawn
+                        && logical_key == Key::Named(NamedKey::Enter);
+                    if respawn {
+                        match spawn_terminal(&self.config, renderer, &self.proxy) {
+                            Ok(state) => *terminal = state,
+                            Err(e) => error!("Failed to spawn the shell: {e}"),
+                        }
+                        window.request_redraw();
+                    }
+                    return;
+                }
+
+                let mut grid = terminal.grid();
+                let Some(input) = input::encode_key(&logical_key, grid.modes()).or(text.as_deref())
+                else {
+                    return;
+                };
+
+                if grid.is_display_scrolled() {
+                    grid.reset_display();
+                    window.request_redraw();
+                }
+                drop(grid);
+
+                terminal.write_content(input);
             }
             _ => {}
         }
@@ -88,8 +225,10 @@ impl ApplicationHandler for Nart {
 }
 
 pub fn init_window() {
-    let event_loop = winit::event_loop::EventLoop::new().unwrap();
-    let mut app = Nart::new();
+    let event_loop = EventLoop::<TerminalEvent>::with_user_event()
+        .build()
+        .unwrap();
+    let mut app = Nart::new(event_loop.create_proxy());
 
     if let Err(e) = event_loop.run_app(&mut app) {
         error!("Failed to run event loop: {e}");